@@ -0,0 +1,83 @@
+//! TinyLFU-style cache admission policy.
+
+use std::hash::Hash;
+
+use super::{Bloom, CountingBloom};
+
+/// Two-level frequency sketch deciding which keys deserve to enter a cache.
+///
+/// The first access to a key within a window only records it in a small
+/// "doorkeeper" bloom filter, so that the many keys that are only accessed
+/// once never reach the main counting filter. Further accesses increment the
+/// counters of the key in the main filter. Every `sample_size` accesses, the
+/// doorkeeper is reset and all the counters are halved, so that frequencies
+/// reflect recent accesses.
+pub struct AdmissionFilter {
+    doorkeeper: Bloom,
+    counts: CountingBloom,
+    sample_size: usize,
+    accesses: usize,
+    threshold: u32,
+}
+
+impl AdmissionFilter {
+    /// Create a new admission filter.
+    /// sample_size is the number of accesses after which frequencies are aged,
+    /// threshold is the estimated frequency a key needs to reach to be admitted,
+    /// fp_p is the wanted rate of false positives of both levels, in ]0.0, 1.0[
+    pub fn new(sample_size: usize, threshold: u32, fp_p: f64) -> AdmissionFilter {
+        AdmissionFilter {
+            doorkeeper: Bloom::new_for_fp_rate(sample_size, fp_p),
+            counts: CountingBloom::new_for_fp_rate(sample_size, fp_p),
+            sample_size,
+            accesses: 0,
+            threshold,
+        }
+    }
+
+    /// Record an access to a key, and return true if its estimated
+    /// frequency within the current window reached the admission threshold.
+    pub fn admit<T>(&mut self, key: T) -> bool
+        where T: Hash
+    {
+        if self.doorkeeper.check_and_set(&key) {
+            self.counts.set(&key);
+        }
+        let frequency = 1 + self.counts.count(&key) as u32;
+        self.accesses += 1;
+        if self.accesses >= self.sample_size {
+            self.reset();
+        }
+        frequency >= self.threshold
+    }
+
+    /// Return the estimated number of accesses to a key within the current window
+    pub fn frequency<T>(&self, key: T) -> u32
+        where T: Hash
+    {
+        self.doorkeeper.check(&key) as u32 + self.counts.count(&key) as u32
+    }
+
+    /// Age the frequencies: clear the doorkeeper and halve all the counters.
+    pub fn reset(&mut self) {
+        self.doorkeeper.clear();
+        self.counts.halve();
+        self.accesses = 0;
+    }
+}
+
+#[test]
+fn admission_filter_admit() {
+    let mut filter = AdmissionFilter::new(1000, 3, 0.01);
+    assert!(!filter.admit("hot"));
+    assert!(!filter.admit("hot"));
+    assert!(filter.admit("hot"));
+    assert_eq!(filter.frequency("hot"), 3);
+    assert!(!filter.admit("cold"));
+    assert_eq!(filter.frequency("never"), 0);
+
+    filter.reset();
+    assert_eq!(filter.frequency("hot"), 1);
+    assert!(!filter.admit("hot"));
+    assert!(filter.admit("hot"));
+}
@@ -0,0 +1,167 @@
+//! Age-partitioned bloom filter (APBF), remembering a sliding window of items.
+//!
+//! The filter is made of k + l slices, each using one hash function. Items are
+//! recorded in the first k slices, and every generation, that is every
+//! generation_size insertions or when `advance` is called, the last slice is
+//! dropped and an empty one is added in front. An item is reported as present
+//! when it is found in k consecutive slices, so it keeps matching while at
+//! least its k slices are in the filter: the window slides one generation at
+//! a time, instead of expiring a whole filter at once like `ExpiringBloom`.
+//!
+//! See "Age-Partitioned Bloom Filters" (Shtul, Baquero, Almeida, 2020).
+
+use std::hash::Hash;
+
+use super::{BitOffsets, BloomHasher, SipKeys};
+
+/// Bloom filter remembering the items recorded during the last generations
+pub struct AgePartitionedBloom {
+    // Slices, indexed by hash function; slices[front] is the newest one
+    slices: Vec<Vec<u64>>,
+    front: usize,
+    slice_bits: u64,
+    k_num: u32,
+    hasher: BloomHasher,
+    generation_size: usize,
+    // Number of items recorded during the current generation
+    generation_items: usize,
+}
+
+impl AgePartitionedBloom {
+    /// Create a new age-partitioned bloom filter.
+    /// k_num is the number of slices an item is recorded in, l_num the number
+    /// of additional slices, and generation_size the number of insertions
+    /// after which the filter advances by one generation.
+    /// Items remain present for the l_num generations following the one they
+    /// were recorded in. Slices are sized so that they are half full when they
+    /// are dropped, so a slice matches an item that wasn't recorded in it with
+    /// a probability of at most 1/2. Items that recently left the window
+    /// still have some of their slices, and are the most likely to be false
+    /// positives.
+    pub fn new(k_num: u32, l_num: u32, generation_size: usize) -> AgePartitionedBloom {
+        assert!(k_num > 0 && generation_size > 0);
+        let slice_bits = ((k_num as f64 * generation_size as f64) / std::f64::consts::LN_2).ceil() as u64;
+        let words = slice_bits.div_ceil(64) as usize;
+        AgePartitionedBloom {
+            slices: (0..k_num + l_num).map(|_| vec![0u64; words]).collect(),
+            front: 0,
+            slice_bits,
+            k_num,
+            hasher: BloomHasher::new(),
+            generation_size,
+            generation_items: 0,
+        }
+    }
+
+    /// Use different SipHash keys, see `Bloom::with_sip_keys`.
+    pub fn with_sip_keys(self, sip_keys: SipKeys) -> AgePartitionedBloom {
+        AgePartitionedBloom {
+            hasher: BloomHasher::from_keys(sip_keys),
+            ..self
+        }
+    }
+
+    /// Record the presence of an item in the current generation, and start
+    /// a new generation once generation_size items have been recorded.
+    pub fn set<T>(&mut self, item: T)
+        where T: Hash
+    {
+        if self.generation_items == self.generation_size {
+            self.advance();
+        }
+        let hashes = self.hasher.hash_pair(&item);
+        for age in 0..self.k_num as usize {
+            let slice = self.slice_index(age);
+            let bit_offset = BitOffsets::nth(hashes, slice as u32, self.slice_bits);
+            self.slices[slice][bit_offset / 64] |= 1u64 << (bit_offset % 64);
+        }
+        self.generation_items += 1;
+    }
+
+    /// Check if an item was recorded within the window.
+    /// There can be false positives, but no false negatives for items
+    /// recorded during the last l_num + 1 generations.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        let hashes = self.hasher.hash_pair(&item);
+        let mut run = 0;
+        for age in 0..self.slices.len() {
+            let slice = self.slice_index(age);
+            let bit_offset = BitOffsets::nth(hashes, slice as u32, self.slice_bits);
+            if self.slices[slice][bit_offset / 64] & (1u64 << (bit_offset % 64)) != 0 {
+                run += 1;
+                if run == self.k_num {
+                    return true;
+                }
+            } else if self.slices.len() - age <= self.k_num as usize {
+                // Not enough slices left for a run
+                return false;
+            } else {
+                run = 0;
+            }
+        }
+        false
+    }
+
+    /// Start a new generation: drop the oldest slice, and add an empty one.
+    /// This can be called periodically, for a window based on time rather
+    /// than on the number of insertions.
+    pub fn advance(&mut self) {
+        self.front = self.slice_index(self.slices.len() - 1);
+        for word in self.slices[self.front].iter_mut() {
+            *word = 0;
+        }
+        self.generation_items = 0;
+    }
+
+    /// Return the number of slices an item is recorded in (k)
+    pub fn number_of_hash_functions(&self) -> u32 {
+        self.k_num
+    }
+
+    /// Return the total number of slices (k + l)
+    pub fn number_of_slices(&self) -> usize {
+        self.slices.len()
+    }
+
+    /// Return the number of bits of each slice
+    pub fn slice_bits(&self) -> u64 {
+        self.slice_bits
+    }
+
+    /// Return the number of insertions after which a new generation starts
+    pub fn generation_size(&self) -> usize {
+        self.generation_size
+    }
+
+    /// Return the index of the slice of a given age
+    fn slice_index(&self, age: usize) -> usize {
+        (self.front + age) % self.slices.len()
+    }
+}
+
+#[test]
+fn age_partitioned_bloom_window() {
+    let mut bloom = AgePartitionedBloom::new(10, 3, 100).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    assert_eq!(bloom.number_of_slices(), 13);
+    assert_eq!(bloom.slice_bits(), 1443);
+    for i in 0..700u32 {
+        bloom.set(i);
+    }
+    // 7 generations were started, and the first 3 ones left the window
+    assert!((300..700u32).all(|i| bloom.check(i)));
+    assert!((700..1000u32).filter(|&i| bloom.check(i)).count() < 5);
+    // Items of the first generation are in 7 slices, and need 3 false
+    // positives in the following ones
+    assert!((0..100u32).filter(|&i| bloom.check(i)).count() < 30);
+
+    bloom.advance();
+    assert!((400..700u32).all(|i| bloom.check(i)));
+    bloom.set(1000u32);
+    assert!(bloom.check(1000u32));
+    for _ in 0..bloom.number_of_slices() {
+        bloom.advance();
+    }
+    assert!(!(0..1001u32).any(|i| bloom.check(i)));
+}
@@ -0,0 +1,126 @@
+//! Common interface of the filters that items can be added to.
+//!
+//! `ApproximateSet` lets applications and benchmarks be generic over the kind
+//! of filter. It only covers what all of them can do: filters built at once,
+//! such as `RibbonFilter`, or that can't be serialized, such as
+//! `CountingBloom`, don't implement it.
+
+use std::hash::Hash;
+
+use super::{AtomicBloom, Bloom, BloomObserver, KeyedBloom, ObservedBloom};
+
+/// Set membership with false positives, but no false negatives
+pub trait ApproximateSet<T: ?Sized> {
+    /// Record the presence of an item, and return true if it was definitely
+    /// new, see `Bloom::insert`.
+    fn insert(&mut self, item: &T) -> bool;
+
+    /// Check if an item may be present in the set.
+    fn contains(&self, item: &T) -> bool;
+
+    /// Return the current rate of false positives, estimated from the
+    /// content of the filter.
+    fn fp_rate_estimate(&self) -> f64;
+
+    /// Serialize the filter, in the format of the filter type.
+    fn serialize(&self) -> Vec<u8>;
+}
+
+impl<T: Hash + ?Sized> ApproximateSet<T> for Bloom {
+    fn insert(&mut self, item: &T) -> bool {
+        Bloom::insert(self, item)
+    }
+
+    fn contains(&self, item: &T) -> bool {
+        self.check(item)
+    }
+
+    fn fp_rate_estimate(&self) -> f64 {
+        self.current_fp_rate()
+    }
+
+    /// Serialize the filter with `Bloom::to_bytes`.
+    fn serialize(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+}
+
+impl<T: Hash + ?Sized> ApproximateSet<T> for AtomicBloom {
+    fn insert(&mut self, item: &T) -> bool {
+        AtomicBloom::insert(self, item)
+    }
+
+    fn contains(&self, item: &T) -> bool {
+        self.check(item)
+    }
+
+    fn fp_rate_estimate(&self) -> f64 {
+        self.current_fp_rate()
+    }
+
+    /// Serialize a copy of the filter with `Bloom::to_bytes`.
+    fn serialize(&self) -> Vec<u8> {
+        self.to_bloom().to_bytes()
+    }
+}
+
+impl<T: Hash + ?Sized> ApproximateSet<T> for KeyedBloom {
+    fn insert(&mut self, item: &T) -> bool {
+        KeyedBloom::insert(self, item)
+    }
+
+    fn contains(&self, item: &T) -> bool {
+        self.check(item)
+    }
+
+    fn fp_rate_estimate(&self) -> f64 {
+        self.bloom().current_fp_rate()
+    }
+
+    /// Serialize the filter of tags with `Bloom::to_bytes`, without the key.
+    fn serialize(&self) -> Vec<u8> {
+        self.bloom().to_bytes()
+    }
+}
+
+impl<T: Hash + ?Sized, O: BloomObserver> ApproximateSet<T> for ObservedBloom<O> {
+    fn insert(&mut self, item: &T) -> bool {
+        ObservedBloom::insert(self, item)
+    }
+
+    fn contains(&self, item: &T) -> bool {
+        self.check(item)
+    }
+
+    fn fp_rate_estimate(&self) -> f64 {
+        self.bloom().current_fp_rate()
+    }
+
+    /// Serialize the filter with `Bloom::to_bytes`, without the observer.
+    fn serialize(&self) -> Vec<u8> {
+        self.bloom().to_bytes()
+    }
+}
+
+#[test]
+fn approximate_set_implementations() {
+    fn exercise<S: ApproximateSet<str>>(mut set: S) -> Vec<u8> {
+        assert_eq!(set.fp_rate_estimate(), 0.0);
+        let keys: Vec<String> = (0..100).map(|i| format!("key-{}", i)).collect();
+        assert!(keys.iter().filter(|key| set.insert(key)).count() > 95);
+        assert!(!set.insert("key-0"));
+        assert!(keys.iter().all(|key| set.contains(key)));
+        assert!((100..1100).filter(|i| set.contains(&format!("key-{}", i))).count() < 30);
+        assert!(set.fp_rate_estimate() > 0.0 && set.fp_rate_estimate() < 0.03);
+        set.serialize()
+    }
+
+    let sip_keys = super::SipKeys([(1, 2), (3, 4)]);
+    let bloom = || Bloom::new_for_fp_rate(100, 0.01).with_sip_keys(sip_keys);
+    let bytes = exercise(bloom());
+    assert!(Bloom::from_bytes(&bytes).unwrap().check("key-42"));
+    assert_eq!(exercise(AtomicBloom::from(bloom())), bytes);
+    assert_eq!(exercise(ObservedBloom::new(bloom(), super::BloomCounters::default())), bytes);
+    let bytes = exercise(KeyedBloom::from_bloom(bloom(), super::SecretKey(1, 2)));
+    assert!(!Bloom::from_bytes(&bytes).unwrap().check("key-42"));
+}
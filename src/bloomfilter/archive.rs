@@ -0,0 +1,173 @@
+//! Zero-copy archival of filters with rkyv.
+//!
+//! `BloomArchive` is the archivable form of a filter. Once archived, for
+//! example into a file that is later mapped into memory, the filter can be
+//! validated with `access` and queried in place through an `ArchivedView`,
+//! without decoding or copying the bitmap.
+
+use std::hash::Hash;
+
+use rkyv::rancor;
+
+use super::serialize::Header;
+use super::{BitOffsets, Bloom, BloomError, BloomHasher, HashAlgorithm, SipKeys};
+
+/// Archivable representation of a filter
+#[derive(Clone, Debug, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[rkyv(archived = ArchivedBloom)]
+pub struct BloomArchive {
+    /// Size of the bitmap, in bits
+    pub bitmap_bits: u64,
+    /// Number of hash functions
+    pub k_num: u32,
+    /// SipHash keys: k0 and k1 of the first function, then of the second one
+    pub sip_keys: [u64; 4],
+    /// Hash functions, see `HashAlgorithm`
+    pub algorithm: u8,
+    /// Number of items recorded in the filter
+    pub items: u64,
+    /// Number of items the filter was designed for
+    pub capacity: u64,
+    /// Bitmap words
+    pub bitmap: Vec<u64>,
+}
+
+impl BloomArchive {
+    /// Serialize the filter with rkyv
+    pub fn to_bytes(&self) -> rkyv::util::AlignedVec {
+        rkyv::to_bytes::<rancor::Error>(self).expect("archiving a filter cannot fail")
+    }
+
+    /// Convert the archive back into a regular, mutable filter.
+    pub fn into_bloom(self) -> Result<Bloom, BloomError> {
+        let header = header(self.bitmap_bits, self.k_num, self.sip_keys, self.algorithm, self.items, self.capacity, self.bitmap.len())?;
+        Ok(header.into_bloom(self.bitmap))
+    }
+}
+
+impl From<&Bloom> for BloomArchive {
+    fn from(bloom: &Bloom) -> BloomArchive {
+        let [(k0, k1), (k2, k3)] = bloom.sip_keys().0;
+        BloomArchive {
+            bitmap_bits: bloom.bitmap_bits,
+            k_num: bloom.k_num,
+            sip_keys: [k0, k1, k2, k3],
+            algorithm: bloom.hash_algorithm().id(),
+            items: bloom.items,
+            capacity: bloom.capacity,
+            bitmap: bloom.bitmap.elems.clone(),
+        }
+    }
+}
+
+/// Validate an archived filter, and return a view of it that can be queried
+/// in place. bytes must be suitably aligned, which is the case of mapped
+/// files and of the output of `BloomArchive::to_bytes`.
+pub fn access(bytes: &[u8]) -> Result<ArchivedView<'_>, BloomError> {
+    let archived = rkyv::access::<ArchivedBloom, rancor::Error>(bytes)
+        .map_err(|_| BloomError::CorruptData { reason: "invalid archive" })?;
+    let hasher = archived.header()?.hasher();
+    Ok(ArchivedView { archived, hasher })
+}
+
+/// Validated archived filter, whose hash functions are set up once, when
+/// it is accessed
+pub struct ArchivedView<'a> {
+    archived: &'a ArchivedBloom,
+    hasher: BloomHasher,
+}
+
+impl<'a> ArchivedView<'a> {
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        let bitmap = &self.archived.bitmap;
+        self.bit_offsets(&item).all(|bit_offset| {
+            bitmap[bit_offset / 64].to_native() & (1u64 << (bit_offset % 64)) != 0
+        })
+    }
+
+    /// Return the number of bits in the filter
+    pub fn number_of_bits(&self) -> u64 {
+        self.archived.bitmap_bits.to_native()
+    }
+
+    /// Return the number of hash functions used for `check`
+    pub fn number_of_hash_functions(&self) -> u32 {
+        self.archived.k_num.to_native()
+    }
+
+    /// Return the number of items recorded in the filter, see `Bloom::approximate_len`
+    pub fn approximate_len(&self) -> usize {
+        self.archived.items.to_native() as usize
+    }
+
+    /// Return the archived filter
+    pub fn archived(&self) -> &'a ArchivedBloom {
+        self.archived
+    }
+
+    /// Copy the archived filter into a regular, mutable filter.
+    pub fn to_bloom(&self) -> Result<Bloom, BloomError> {
+        rkyv::deserialize::<BloomArchive, rancor::Error>(self.archived)
+            .map_err(|_| BloomError::CorruptData { reason: "invalid archive" })?
+            .into_bloom()
+    }
+
+    fn bit_offsets<T>(&self, item: &T) -> BitOffsets
+        where T: Hash
+    {
+        self.hasher.bit_offsets(item, self.number_of_hash_functions(), self.number_of_bits())
+    }
+}
+
+impl ArchivedBloom {
+    fn header(&self) -> Result<Header, BloomError> {
+        let sip_keys = [
+            self.sip_keys[0].to_native(),
+            self.sip_keys[1].to_native(),
+            self.sip_keys[2].to_native(),
+            self.sip_keys[3].to_native(),
+        ];
+        header(self.bitmap_bits.to_native(), self.k_num.to_native(), sip_keys, self.algorithm,
+               self.items.to_native(), self.capacity.to_native(), self.bitmap.len())
+    }
+}
+
+/// Check the parameters of an archived filter, and return them as a header
+fn header(bitmap_bits: u64, k_num: u32, sip_keys: [u64; 4], algorithm: u8, items: u64, capacity: u64, words: usize) -> Result<Header, BloomError> {
+    let algorithm = HashAlgorithm::from_id(algorithm)
+        .ok_or(BloomError::CorruptData { reason: "unsupported hash algorithm" })?;
+    let [k0, k1, k2, k3] = sip_keys;
+    let header = Header {
+        bitmap_bits,
+        k_num,
+        sip_keys: SipKeys([(k0, k1), (k2, k3)]),
+        algorithm,
+        items: Some(items),
+        capacity: Some(capacity),
+    };
+    header.validate(Some(words * 8))?;
+    Ok(header)
+}
+
+#[test]
+fn bloom_archive_access() {
+    let mut bloom = Bloom::new(100, 50).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    for i in 0..50u32 {
+        bloom.set(i);
+    }
+    let bytes = BloomArchive::from(&bloom).to_bytes();
+    let archived = access(&bytes).unwrap();
+    assert_eq!(archived.number_of_bits(), bloom.number_of_bits());
+    assert_eq!(archived.approximate_len(), bloom.approximate_len());
+    assert!((0..200u32).all(|i| archived.check(i) == bloom.check(i)));
+    assert_eq!(archived.to_bloom().unwrap().to_bytes(), bloom.to_bytes());
+
+    let mut bad = BloomArchive::from(&bloom);
+    bad.bitmap.pop();
+    assert!(access(&bad.to_bytes()).is_err());
+    assert!(bad.into_bloom().is_err());
+}
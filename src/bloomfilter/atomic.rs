@@ -0,0 +1,176 @@
+//! Bloom filter with an atomic bitmap, that can be updated concurrently.
+
+use std::hash::Hash;
+use std::sync::atomic::{self, AtomicU64, Ordering};
+
+use super::{BitOffsets, BitVecJournal, Bloom, BloomHasher};
+
+/// Bloom filter whose bitmap words are atomics, so that items can be
+/// recorded and checked concurrently from shared references.
+pub struct AtomicBloom {
+    bitmap: Vec<AtomicU64>,
+    bitmap_bits: u64,
+    k_num: u32,
+    hasher: BloomHasher,
+    capacity: u64,
+}
+
+impl AtomicBloom {
+    /// Create a new bloom filter structure.
+    /// bitmap_size is the size in bytes (not bits) that will be allocated in memory
+    /// items_count is an estimation of the maximum number of items to store.
+    pub fn new(bitmap_size: usize, items_count: usize) -> AtomicBloom {
+        AtomicBloom::from(Bloom::new(bitmap_size, items_count))
+    }
+
+    /// Create a new bloom filter structure.
+    /// items_count is an estimation of the maximum number of items to store.
+    /// fp_p is the wanted rate of false positives, in ]0.0, 1.0[
+    pub fn new_for_fp_rate(items_count: usize, fp_p: f64) -> AtomicBloom {
+        AtomicBloom::from(Bloom::new_for_fp_rate(items_count, fp_p))
+    }
+
+    /// Record the presence of an item.
+    /// Bits are set with atomic read-modify-write operations, so no update
+    /// is ever lost, even when several threads write to the same word.
+    pub fn set<T>(&self, item: T)
+        where T: Hash
+    {
+        for bit_offset in self.bit_offsets(&item) {
+            self.bitmap[bit_offset / 64].fetch_or(1u64 << (bit_offset % 64), Ordering::Release);
+        }
+    }
+
+    /// Record the presence of an item, and return true if it was definitely
+    /// new, that is if this call set at least one bit.
+    pub fn insert<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        let mut new = false;
+        for bit_offset in self.bit_offsets(&item) {
+            let mask = 1u64 << (bit_offset % 64);
+            new |= self.bitmap[bit_offset / 64].fetch_or(mask, Ordering::Release) & mask == 0;
+        }
+        new
+    }
+
+    /// Record the presence of an item, favoring throughput over consistency.
+    ///
+    /// Words are updated with relaxed loads and plain stores, instead of
+    /// read-modify-write operations. If another thread stores to the same
+    /// word between the load and the store, the bits it set are overwritten
+    /// and lost for good, and the items they belong to can then be reported
+    /// as absent. Use this only when rare false negatives are acceptable,
+    /// for example when a single thread writes to the filter.
+    pub fn set_relaxed<T>(&self, item: T)
+        where T: Hash
+    {
+        for bit_offset in self.bit_offsets(&item) {
+            let word = &self.bitmap[bit_offset / 64];
+            let mask = 1u64 << (bit_offset % 64);
+            let value = word.load(Ordering::Relaxed);
+            if value & mask == 0 {
+                word.store(value | mask, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Order the updates previously made with `set_relaxed` by this thread
+    /// before the following ones: a thread that sees a bit set by this
+    /// thread after `flush` also sees all the bits stored before it.
+    pub fn flush(&self) {
+        atomic::fence(Ordering::Release);
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives for items
+    /// recorded with `set`, see `set_relaxed`.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        self.bit_offsets(&item).all(|bit_offset| {
+            self.bitmap[bit_offset / 64].load(Ordering::Acquire) & (1u64 << (bit_offset % 64)) != 0
+        })
+    }
+
+    /// Return the number of bits in the filter
+    pub fn number_of_bits(&self) -> u64 {
+        self.bitmap_bits
+    }
+
+    /// Return the number of hash functions used for `check` and `set`
+    pub fn number_of_hash_functions(&self) -> u32 {
+        self.k_num
+    }
+
+    /// Return the probability that an item that was never recorded is
+    /// reported as present, given the current fill ratio.
+    pub fn current_fp_rate(&self) -> f64 {
+        let ones: u64 = self.bitmap.iter().map(|word| word.load(Ordering::Relaxed).count_ones() as u64).sum();
+        (ones as f64 / self.bitmap_bits as f64).powi(self.k_num as i32)
+    }
+
+    /// Copy the current content of the filter into a regular filter,
+    /// for example to serialize it.
+    pub fn to_bloom(&self) -> Bloom {
+        let elems = self.bitmap.iter().map(|word| word.load(Ordering::Acquire)).collect();
+        let mut bloom = Bloom {
+            bitmap: BitVecJournal::from_vec(elems),
+            bitmap_bits: self.bitmap_bits,
+            k_num: self.k_num,
+            hasher: self.hasher.clone(),
+            items: 0,
+            capacity: self.capacity,
+        };
+        bloom.items = bloom.estimate_items();
+        bloom
+    }
+
+    fn bit_offsets<T>(&self, item: &T) -> BitOffsets
+        where T: Hash
+    {
+        self.hasher.bit_offsets(item, self.k_num, self.bitmap_bits)
+    }
+}
+
+impl From<Bloom> for AtomicBloom {
+    fn from(bloom: Bloom) -> AtomicBloom {
+        AtomicBloom {
+            bitmap: bloom.bitmap.elems.into_iter().map(AtomicU64::new).collect(),
+            bitmap_bits: bloom.bitmap_bits,
+            k_num: bloom.k_num,
+            hasher: bloom.hasher,
+            capacity: bloom.capacity,
+        }
+    }
+}
+
+#[test]
+fn atomic_bloom_concurrent_set() {
+    let bloom = std::sync::Arc::new(AtomicBloom::new_for_fp_rate(4000, 0.01));
+    let writers: Vec<_> = (0..4u32).map(|t| {
+        let bloom = bloom.clone();
+        std::thread::spawn(move || {
+            for i in 0..1000u32 {
+                bloom.set(t * 1000 + i);
+            }
+        })
+    }).collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert!((0..4000u32).all(|i| bloom.check(i)));
+
+    let snapshot = bloom.to_bloom();
+    assert!((0..4000u32).all(|i| snapshot.check(i)));
+}
+
+#[test]
+fn atomic_bloom_relaxed_set() {
+    let bloom = AtomicBloom::new_for_fp_rate(100, 0.01);
+    for i in 0..100u32 {
+        bloom.set_relaxed(i);
+    }
+    bloom.flush();
+    assert!((0..100u32).all(|i| bloom.check(i)));
+}
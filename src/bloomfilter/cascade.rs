@@ -0,0 +1,122 @@
+//! Filter cascade, encoding an exact set compactly (CRLite-style).
+//!
+//! Given the set of included items and the rest of a known universe (the
+//! excluded items), the first level is a bloom filter of the included items.
+//! Its false positives among the excluded items are recorded in the second
+//! level, whose false positives among the included items are recorded in the
+//! third level, and so on until a level has no false positives left. For an
+//! item of the universe, the first level that doesn't contain it tells whether
+//! it is included, without any false positives.
+
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+
+use super::{Bloom, BloomError};
+
+/// Maximum number of levels of a cascade.
+/// Deeper cascades can only be caused by items both included and excluded.
+const MAX_LEVELS: usize = 128;
+
+/// Rate of false positives of the levels after the first one
+const DEEP_LEVELS_FP_RATE: f64 = 0.5;
+
+/// Layered bloom filters giving exact answers for the items of a known universe
+pub struct FilterCascade {
+    levels: Vec<Bloom>,
+}
+
+impl FilterCascade {
+    /// Build a cascade out of the included and excluded items, that must be disjoint.
+    /// fp_p is the rate of false positives of the first level, in ]0.0, 1.0[
+    pub fn build<T>(included: &[T], excluded: &[T], fp_p: f64) -> FilterCascade
+        where T: Hash
+    {
+        let mut levels = Vec::new();
+        let mut inserted: Vec<&T> = included.iter().collect();
+        let mut tested: Vec<&T> = excluded.iter().collect();
+        while !inserted.is_empty() {
+            assert!(levels.len() < MAX_LEVELS, "included and excluded items must be disjoint");
+            let level = levels.len() as u32;
+            let fp_p = if level == 0 { fp_p } else { DEEP_LEVELS_FP_RATE };
+            let mut bloom = Bloom::new_for_fp_rate(inserted.len(), fp_p);
+            for item in &inserted {
+                bloom.set((level, item));
+            }
+            let false_positives = tested.into_iter().filter(|item| bloom.check((level, item))).collect();
+            levels.push(bloom);
+            tested = inserted;
+            inserted = false_positives;
+        }
+        FilterCascade { levels }
+    }
+
+    /// Check if an item is included.
+    /// The answer is exact for items that were passed to `build`, either as
+    /// included or as excluded items. Other items can be false positives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        for (level, bloom) in self.levels.iter().enumerate() {
+            if !bloom.check((level as u32, &item)) {
+                return level % 2 == 1;
+            }
+        }
+        self.levels.len() % 2 == 1
+    }
+
+    /// Return the number of levels of the cascade
+    pub fn number_of_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Serialize the cascade into a writer, as the number of levels
+    /// (u32, little-endian) followed by the serialized levels.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&(self.levels.len() as u32).to_le_bytes())?;
+        for bloom in &self.levels {
+            bloom.write_to(w)?;
+        }
+        Ok(())
+    }
+
+    /// Deserialize a cascade previously serialized with `write_to`.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<FilterCascade> {
+        let mut count = [0u8; 4];
+        r.read_exact(&mut count)?;
+        let count = u32::from_le_bytes(count) as usize;
+        if count > MAX_LEVELS {
+            return Err(BloomError::corrupt("too many cascade levels"));
+        }
+        let levels = (0..count).map(|_| Bloom::read_from(r)).collect::<io::Result<Vec<Bloom>>>()?;
+        Ok(FilterCascade { levels })
+    }
+
+    /// Serialize the cascade into a vector of bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes).expect("writing to a vector cannot fail");
+        bytes
+    }
+
+    /// Deserialize a cascade previously serialized with `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<FilterCascade> {
+        FilterCascade::read_from(&mut &bytes[..])
+    }
+}
+
+#[test]
+fn filter_cascade_exact() {
+    let included: Vec<u32> = (0..1000).filter(|i| i % 7 == 0).collect();
+    let excluded: Vec<u32> = (0..1000).filter(|i| i % 7 != 0).collect();
+    let cascade = FilterCascade::build(&included, &excluded, 0.1);
+    assert!(cascade.number_of_levels() > 1);
+    assert!(included.iter().all(|i| cascade.check(i)));
+    assert!(excluded.iter().all(|i| !cascade.check(i)));
+
+    let cascade = FilterCascade::from_bytes(&cascade.to_bytes()).unwrap();
+    assert!(included.iter().all(|i| cascade.check(i)));
+    assert!(excluded.iter().all(|i| !cascade.check(i)));
+
+    let empty = FilterCascade::build(&[] as &[u32], &excluded, 0.1);
+    assert!(excluded.iter().all(|i| !empty.check(i)));
+}
@@ -0,0 +1,221 @@
+//! Bloom filters of Cassandra SSTables, as stored in their Filter.db component.
+//!
+//! The component is the number of hash functions, as a big-endian i32,
+//! followed by the bitmap: its number of 64-bit words, as a big-endian i32,
+//! and the words. Bit i of the bitmap is bit i % 8 of byte i / 8 of the
+//! bitmap in memory, and depending on the version of the SSTable format:
+//!
+//! * before 4.0 (versions before "na"), each group of 8 bytes is written as
+//!   a big-endian long whose bytes are the group in reverse order, that is
+//!   bit i of the bitmap is bit i % 64 of word i / 64
+//! * since 4.0, the bytes are written as they are in memory
+//!
+//! Partition keys are hashed with the x64 128-bit variant of MurmurHash3,
+//! seeded with 0, giving h1 and h2. Probe i is (h2 + i * h1) modulo the
+//! number of bits of the bitmap, computed on signed 64-bit integers, and
+//! made positive. SSTables written before 3.0 (versions before "ma") swap
+//! h1 and h2.
+//!
+//! Filters are only read: the `check` API queries them offline with the
+//! serialized partition keys, as the SSTables store them. HBase filters,
+//! whose blocks are stored inside HFiles, are not supported.
+
+use std::io::{self, Read};
+
+use super::BloomError;
+
+/// Number of bitmap words read at once
+const CHUNK_WORDS: usize = 1024;
+
+/// Version of the SSTable format the filter was written with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CassandraFormat {
+    /// Before 3.0 (versions before "ma"), with swapped hashes
+    Legacy,
+    /// 3.0 to 3.11 (versions "ma" to "md")
+    V3,
+    /// 4.0 and later (versions "na" and later)
+    V4,
+}
+
+/// MurmurHash3, x64 128-bit variant, as implemented by Cassandra.
+/// Cassandra sign-extends the trailing bytes of keys whose length isn't a
+/// multiple of 16, so the hashes of keys ending with bytes above 0x7f differ
+/// from the reference implementation.
+pub fn murmur3_x64_128(key: &[u8], seed: u64) -> [u64; 2] {
+    const C1: u64 = 0x87c37b91114253d5;
+    const C2: u64 = 0x4cf5ad432745937f;
+    let (mut h1, mut h2) = (seed, seed);
+    let mut blocks = key.chunks_exact(16);
+    for block in &mut blocks {
+        let mut k1 = u64::from_le_bytes([block[0], block[1], block[2], block[3], block[4], block[5], block[6], block[7]]);
+        let mut k2 = u64::from_le_bytes([block[8], block[9], block[10], block[11], block[12], block[13], block[14], block[15]]);
+        k1 = k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(27).wrapping_add(h2).wrapping_mul(5).wrapping_add(0x52dce729);
+        k2 = k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 ^= k2;
+        h2 = h2.rotate_left(31).wrapping_add(h1).wrapping_mul(5).wrapping_add(0x38495ab5);
+    }
+    let tail = blocks.remainder();
+    let (mut k1, mut k2) = (0u64, 0u64);
+    for (i, &byte) in tail.iter().enumerate().rev() {
+        let byte = byte as i8 as i64 as u64;
+        if i >= 8 {
+            k2 ^= byte << (8 * (i - 8));
+        } else {
+            k1 ^= byte << (8 * i);
+        }
+    }
+    if tail.len() > 8 {
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    }
+    if !tail.is_empty() {
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    }
+    h1 ^= key.len() as u64;
+    h2 ^= key.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    h1 = fmix(h1);
+    h2 = fmix(h2);
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    [h1, h2]
+}
+
+fn fmix(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51afd7ed558ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ceb9fe1a85ec53);
+    k ^= k >> 33;
+    k
+}
+
+/// Bloom filter read from the Filter.db component of a Cassandra SSTable
+pub struct CassandraBloom {
+    elems: Vec<u64>,
+    hash_count: u32,
+    format: CassandraFormat,
+}
+
+impl CassandraBloom {
+    /// Read a filter written with the given version of the SSTable format.
+    /// The bitmap is read in chunks, and only grows as data is actually read.
+    pub fn read_from<R: Read>(r: &mut R, format: CassandraFormat) -> io::Result<CassandraBloom> {
+        let hash_count = read_i32(r)?;
+        let words = read_i32(r)?;
+        if hash_count <= 0 || words <= 0 {
+            return Err(BloomError::InvalidParams.into());
+        }
+        let words = words as usize;
+        let mut elems = Vec::with_capacity(words.min(CHUNK_WORDS));
+        let mut buf = vec![0u8; CHUNK_WORDS * 8];
+        while elems.len() < words {
+            let n = (words - elems.len()).min(CHUNK_WORDS);
+            let chunk = &mut buf[..n * 8];
+            r.read_exact(chunk)?;
+            elems.extend(chunk.chunks(8).map(|bytes| {
+                let mut word = [0u8; 8];
+                word.copy_from_slice(bytes);
+                match format {
+                    CassandraFormat::Legacy | CassandraFormat::V3 => u64::from_be_bytes(word),
+                    CassandraFormat::V4 => u64::from_le_bytes(word),
+                }
+            }));
+        }
+        Ok(CassandraBloom {
+            elems,
+            hash_count: hash_count as u32,
+            format,
+        })
+    }
+
+    /// Read a filter from the content of a Filter.db component.
+    pub fn from_bytes(bytes: &[u8], format: CassandraFormat) -> io::Result<CassandraBloom> {
+        CassandraBloom::read_from(&mut &bytes[..], format)
+    }
+
+    /// Check if a serialized partition key may be present in the SSTable.
+    /// There can be false positives, but no false negatives.
+    pub fn check(&self, key: &[u8]) -> bool {
+        let [h1, h2] = murmur3_x64_128(key, 0);
+        let (mut base, inc) = match self.format {
+            CassandraFormat::Legacy => (h1 as i64, h2 as i64),
+            CassandraFormat::V3 | CassandraFormat::V4 => (h2 as i64, h1 as i64),
+        };
+        let max = self.number_of_bits() as i64;
+        for _ in 0..self.hash_count {
+            let bit_offset = (base % max).unsigned_abs() as usize;
+            if self.elems[bit_offset / 64] & (1u64 << (bit_offset % 64)) == 0 {
+                return false;
+            }
+            base = base.wrapping_add(inc);
+        }
+        true
+    }
+
+    /// Return the number of bits in the filter
+    pub fn number_of_bits(&self) -> u64 {
+        self.elems.len() as u64 * 64
+    }
+
+    /// Return the number of hash functions used for `check`
+    pub fn number_of_hash_functions(&self) -> u32 {
+        self.hash_count
+    }
+}
+
+fn read_i32<R: Read>(r: &mut R) -> io::Result<i32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(i32::from_be_bytes(buf))
+}
+
+#[test]
+fn cassandra_murmur3() {
+    assert_eq!(murmur3_x64_128(b"", 0), [0, 0]);
+    assert_eq!(murmur3_x64_128(b"The quick brown fox jumps over the lazy dog", 0),
+               [0xe34bbc7bbc071b6c, 0x7a433ca9c49a9347]);
+}
+
+#[test]
+fn cassandra_bloom_formats() {
+    let keys: Vec<Vec<u8>> = (0..100u32).map(|i| format!("partition-{}", i).into_bytes()).collect();
+    // Filter built the way Cassandra builds one: 1024 bits, 5 hash functions
+    let build = |format: CassandraFormat| {
+        let mut memory = [0u8; 128];
+        for key in keys.iter() {
+            let [h1, h2] = murmur3_x64_128(key, 0);
+            let (mut base, inc) = if format == CassandraFormat::Legacy { (h1 as i64, h2 as i64) } else { (h2 as i64, h1 as i64) };
+            for _ in 0..5 {
+                let index = (base % 1024).unsigned_abs() as usize;
+                memory[index / 8] |= 1 << (index % 8);
+                base = base.wrapping_add(inc);
+            }
+        }
+        let mut file = vec![0, 0, 0, 5, 0, 0, 0, 16];
+        for group in memory.chunks(8) {
+            if format == CassandraFormat::V4 {
+                file.extend(group.iter());
+            } else {
+                file.extend(group.iter().rev());
+            }
+        }
+        file
+    };
+    for format in [CassandraFormat::Legacy, CassandraFormat::V3, CassandraFormat::V4] {
+        let bloom = CassandraBloom::from_bytes(&build(format), format).unwrap();
+        assert_eq!(bloom.number_of_bits(), 1024);
+        assert_eq!(bloom.number_of_hash_functions(), 5);
+        assert!(keys.iter().all(|key| bloom.check(key)));
+        assert!((100..1100u32).filter(|i| bloom.check(format!("partition-{}", i).as_bytes())).count() < 100);
+    }
+    let legacy = CassandraBloom::from_bytes(&build(CassandraFormat::Legacy), CassandraFormat::V3).unwrap();
+    assert!(!keys.iter().all(|key| legacy.check(key)));
+
+    assert!(CassandraBloom::from_bytes(&[0, 0, 0, 5, 0, 0, 0, 16, 0], CassandraFormat::V4).is_err());
+    let err = CassandraBloom::from_bytes(&[0, 0, 0, 0, 0, 0, 0, 16], CassandraFormat::V4).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::InvalidParams));
+}
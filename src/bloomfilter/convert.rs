@@ -0,0 +1,82 @@
+//! Conversions between the bitmap of a filter and other bit containers.
+//!
+//! Bit i of the bitmap is bit i of the `BitVec` or element i of the
+//! `Vec<bool>`. These don't carry the parameters of the filter: the number
+//! of hash functions has to be given when converting back, and SipHash keys
+//! set with `with_sip_keys`.
+
+use bit_vec::BitVec;
+
+use super::{BitVecJournal, Bloom};
+
+impl Bloom {
+    /// Return the bitmap as 64-bit words: bit i of the bitmap is bit i % 64
+    /// of word i / 64. This is what `from_parts` expects.
+    pub fn as_parts(&self) -> &[u64] {
+        &self.bitmap.elems
+    }
+
+    /// Copy the bitmap into a `BitVec` of `number_of_bits` bits.
+    pub fn to_bit_vec(&self) -> BitVec {
+        BitVec::from_fn(self.bitmap_bits as usize, |i| self.bitmap.get(i))
+    }
+
+    /// Create a filter from a bitmap, for example one returned by `to_bit_vec`,
+    /// using k_num hash functions.
+    pub fn from_bit_vec(bits: &BitVec, k_num: u32) -> Bloom {
+        Bloom::from_bits(bits.len(), bits.iter(), k_num)
+    }
+
+    /// Copy the bitmap into a vector of `number_of_bits` booleans.
+    pub fn to_bools(&self) -> Vec<bool> {
+        (0..self.bitmap_bits as usize).map(|i| self.bitmap.get(i)).collect()
+    }
+
+    /// Create a filter from a bitmap, for example one returned by `to_bools`,
+    /// using k_num hash functions.
+    pub fn from_bools(bits: &[bool], k_num: u32) -> Bloom {
+        Bloom::from_bits(bits.len(), bits.iter().copied(), k_num)
+    }
+
+    fn from_bits<I>(len: usize, bits: I, k_num: u32) -> Bloom
+        where I: Iterator<Item = bool>
+    {
+        assert!(len > 0 && k_num > 0);
+        let mut elems = vec![0u64; len.div_ceil(64)];
+        for (i, bit) in bits.enumerate() {
+            if bit {
+                elems[i / 64] |= 1u64 << (i % 64);
+            }
+        }
+        let mut bloom = Bloom::from_bitmap(BitVecJournal::from_vec(elems), len as u64, k_num);
+        bloom.items = bloom.estimate_items();
+        bloom
+    }
+}
+
+impl From<&Bloom> for BitVec {
+    fn from(bloom: &Bloom) -> BitVec {
+        bloom.to_bit_vec()
+    }
+}
+
+#[test]
+fn bloom_bit_vec_conversions() {
+    let mut bloom = Bloom::new(13, 10).with_sip_keys(super::SipKeys([(1, 2), (3, 4)]));
+    bloom.insert_all(0..10u32);
+    let k_num = bloom.number_of_hash_functions();
+
+    let bits = BitVec::from(&bloom);
+    assert_eq!(bits.len(), 104);
+    assert!((0..104).all(|i| bits[i] == (bloom.as_parts()[i / 64] & (1 << (i % 64)) != 0)));
+    let restored = Bloom::from_bit_vec(&bits, k_num).with_sip_keys(bloom.sip_keys());
+    assert_eq!(restored.number_of_bits(), 104);
+    assert_eq!(restored.as_parts(), bloom.as_parts());
+    assert!((0..10u32).all(|i| restored.check(i)));
+    assert!(restored.bitmap.journal.is_empty());
+
+    let bools = bloom.to_bools();
+    assert_eq!(bools, bits.iter().collect::<Vec<bool>>());
+    let restored = Bloom::from_bools(&bools, k_num).with_sip_keys(bloom.sip_keys());
+    assert_eq!(restored.to_bytes(), Bloom::from_bit_vec(&bits, k_num).with_sip_keys(bloom.sip_keys()).to_bytes());
+}
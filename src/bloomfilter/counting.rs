@@ -0,0 +1,364 @@
+//! Counting bloom filter, supporting removal of items.
+
+use std::hash::Hash;
+
+use super::{BitOffsets, Bloom, BloomError, BloomHasher, SipKeys};
+
+/// Number of bits of the counters of a `CountingBloom`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CounterWidth {
+    /// 4-bit counters, two per byte, counting up to 15
+    Four,
+    /// 8-bit counters, counting up to 255
+    Eight,
+    /// 16-bit counters, counting up to 65535
+    Sixteen,
+}
+
+impl CounterWidth {
+    /// Return the largest value of a counter
+    pub fn max_value(self) -> u16 {
+        match self {
+            CounterWidth::Four => 0xf,
+            CounterWidth::Eight => 0xff,
+            CounterWidth::Sixteen => 0xffff,
+        }
+    }
+
+    /// Return the number of bytes needed to store counters_count counters
+    fn bytes_for(self, counters_count: usize) -> usize {
+        match self {
+            CounterWidth::Four => counters_count.div_ceil(2),
+            CounterWidth::Eight => counters_count,
+            CounterWidth::Sixteen => counters_count * 2,
+        }
+    }
+}
+
+/// Counters packed into bytes
+struct Counters {
+    bytes: Vec<u8>,
+    len: usize,
+    width: CounterWidth,
+}
+
+impl Counters {
+    fn new(len: usize, width: CounterWidth) -> Counters {
+        Counters {
+            bytes: vec![0u8; width.bytes_for(len)],
+            len,
+            width,
+        }
+    }
+
+    fn get(&self, i: usize) -> u16 {
+        match self.width {
+            CounterWidth::Four => ((self.bytes[i / 2] >> (i % 2 * 4)) & 0xf) as u16,
+            CounterWidth::Eight => self.bytes[i] as u16,
+            CounterWidth::Sixteen => u16::from_le_bytes([self.bytes[i * 2], self.bytes[i * 2 + 1]]),
+        }
+    }
+
+    fn put(&mut self, i: usize, value: u16) {
+        match self.width {
+            CounterWidth::Four => {
+                let shift = i % 2 * 4;
+                let byte = &mut self.bytes[i / 2];
+                *byte = (*byte & !(0xf << shift)) | ((value as u8) << shift);
+            }
+            CounterWidth::Eight => self.bytes[i] = value as u8,
+            CounterWidth::Sixteen => self.bytes[i * 2..i * 2 + 2].copy_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.len).map(move |i| self.get(i))
+    }
+}
+
+/// Counting bloom filter: every bit of the bitmap is replaced with a
+/// saturating counter, so that items can also be removed.
+/// Counters are 8-bit unless another width is chosen with `with_counter_width`.
+/// A counter that reached its maximum value is never decremented again,
+/// since the number of items it accounts for is then unknown.
+/// Use `try_set` instead of `set` to keep all the counters exact.
+pub struct CountingBloom {
+    counters: Counters,
+    k_num: u32,
+    hasher: BloomHasher,
+    overflows: u64,
+}
+
+impl CountingBloom {
+    /// Create a new counting bloom filter structure.
+    /// counters_count is the number of 8-bit counters that will be allocated in memory
+    /// items_count is an estimation of the maximum number of items to store.
+    pub fn new(counters_count: usize, items_count: usize) -> CountingBloom {
+        assert!(counters_count > 0 && items_count > 0);
+        CountingBloom {
+            counters: Counters::new(counters_count, CounterWidth::Eight),
+            k_num: Bloom::optimal_k_num(counters_count as u64, items_count),
+            hasher: BloomHasher::new(),
+            overflows: 0,
+        }
+    }
+
+    /// Create a new counting bloom filter structure.
+    /// items_count is an estimation of the maximum number of items to store.
+    /// fp_p is the wanted rate of false positives, in ]0.0, 1.0[
+    pub fn new_for_fp_rate(items_count: usize, fp_p: f64) -> CountingBloom {
+        let counters_count = Bloom::compute_bitmap_size(items_count, fp_p) * 8;
+        CountingBloom::new(counters_count, items_count)
+    }
+
+    /// Use different SipHash keys, for example random ones.
+    /// This must be called on an empty filter.
+    pub fn with_sip_keys(self, sip_keys: SipKeys) -> CountingBloom {
+        CountingBloom {
+            hasher: BloomHasher::from_keys(sip_keys),
+            ..self
+        }
+    }
+
+    /// Use counters of a different width.
+    /// This must be called on an empty filter.
+    pub fn with_counter_width(self, width: CounterWidth) -> CountingBloom {
+        CountingBloom {
+            counters: Counters::new(self.counters.len, width),
+            ..self
+        }
+    }
+
+    /// Record the presence of an item.
+    /// Counters that are already saturated are left unchanged, and counted
+    /// as overflows.
+    pub fn set<T>(&mut self, item: T)
+        where T: Hash
+    {
+        let max = self.counters.width.max_value();
+        for offset in self.offsets(&item) {
+            let counter = self.counters.get(offset);
+            if counter != max {
+                self.counters.put(offset, counter + 1);
+            } else {
+                self.overflows += 1;
+            }
+        }
+    }
+
+    /// Record the presence of an item, unless one of its counters would
+    /// saturate. The filter is then left unchanged, so that all the counters
+    /// stay exact and removing any recorded item is always sound.
+    pub fn try_set<T>(&mut self, item: T) -> Result<(), BloomError>
+        where T: Hash
+    {
+        let max = self.counters.width.max_value();
+        let mut offsets: Vec<usize> = self.offsets(&item).collect();
+        // An offset can appear twice, and its counter is then incremented twice
+        offsets.sort_unstable();
+        let mut i = 0;
+        while i < offsets.len() {
+            let repeats = offsets[i..].iter().take_while(|&&offset| offset == offsets[i]).count();
+            if self.counters.get(offsets[i]) as usize + repeats >= max as usize {
+                return Err(BloomError::CounterOverflow);
+            }
+            i += repeats;
+        }
+        for offset in offsets {
+            let counter = self.counters.get(offset);
+            self.counters.put(offset, counter + 1);
+        }
+        Ok(())
+    }
+
+    /// Remove an item previously recorded with `set`.
+    /// Returns false, leaving the filter unchanged, if the item was not present.
+    /// Removing an item that was never recorded, but happens to be a false
+    /// positive, introduces false negatives.
+    pub fn unset<T>(&mut self, item: T) -> bool
+        where T: Hash
+    {
+        if !self.check(&item) {
+            return false;
+        }
+        let max = self.counters.width.max_value();
+        for offset in self.offsets(&item) {
+            let counter = self.counters.get(offset);
+            if counter != max {
+                self.counters.put(offset, counter - 1);
+            }
+        }
+        true
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        self.offsets(&item).all(|offset| self.counters.get(offset) > 0)
+    }
+
+    /// Return an upper bound of the number of times an item was recorded
+    pub fn count<T>(&self, item: T) -> u16
+        where T: Hash
+    {
+        self.offsets(&item).map(|offset| self.counters.get(offset)).min().unwrap_or(0)
+    }
+
+    /// Remove the items of another filter from this one, as if `unset` had
+    /// been called for each of them. Counters saturate at zero.
+    pub fn subtract(&mut self, other: &CountingBloom) -> Result<(), BloomError> {
+        self.check_compatible(other)?;
+        let max = self.counters.width.max_value();
+        for i in 0..self.counters.len {
+            let counter = self.counters.get(i);
+            if counter != max {
+                self.counters.put(i, counter.saturating_sub(other.counters.get(i)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimate the number of items of this filter that are not in another one.
+    pub fn difference(&self, other: &CountingBloom) -> Result<f64, BloomError> {
+        self.check_compatible(other)?;
+        let sum = self.counters.iter().zip(other.counters.iter())
+            .fold(0u64, |acc, (counter, other)| acc + counter.saturating_sub(other) as u64);
+        Ok(sum as f64 / self.k_num as f64)
+    }
+
+    /// Halve all the counters, to age the recorded items.
+    /// Saturated counters are halved too.
+    pub fn halve(&mut self) {
+        for i in 0..self.counters.len {
+            let counter = self.counters.get(i);
+            self.counters.put(i, counter / 2);
+        }
+    }
+
+    /// Estimate the number of items in the filter
+    pub fn estimated_len(&self) -> f64 {
+        let sum = self.counters.iter().fold(0u64, |acc, counter| acc + counter as u64);
+        sum as f64 / self.k_num as f64
+    }
+
+    /// Return the number of counters in the filter
+    pub fn number_of_counters(&self) -> u64 {
+        self.counters.len as u64
+    }
+
+    /// Return the number of times `set` found a counter already saturated
+    pub fn overflows(&self) -> u64 {
+        self.overflows
+    }
+
+    /// Return the width of the counters
+    pub fn counter_width(&self) -> CounterWidth {
+        self.counters.width
+    }
+
+    /// Return the number of hash functions used for `check` and `set`
+    pub fn number_of_hash_functions(&self) -> u32 {
+        self.k_num
+    }
+
+    fn check_compatible(&self, other: &CountingBloom) -> Result<(), BloomError> {
+        if self.counters.len != other.counters.len || self.counters.width != other.counters.width ||
+           self.k_num != other.k_num {
+            return Err(BloomError::SizeMismatch);
+        }
+        if !self.hasher.is_compatible(&other.hasher) {
+            return Err(BloomError::IncompatibleSeeds);
+        }
+        Ok(())
+    }
+
+    fn offsets<T>(&self, item: &T) -> BitOffsets
+        where T: Hash
+    {
+        self.hasher.bit_offsets(item, self.k_num, self.counters.len as u64)
+    }
+}
+
+#[test]
+fn counting_bloom_unset() {
+    let mut bloom = CountingBloom::new_for_fp_rate(100, 0.01);
+    bloom.set("a");
+    bloom.set("a");
+    bloom.set("b");
+    assert_eq!(bloom.count("a"), 2);
+    assert!(bloom.unset("a"));
+    assert!(bloom.check("a"));
+    assert!(bloom.unset("a"));
+    assert!(!bloom.check("a"));
+    assert!(!bloom.unset("a"));
+    assert!(bloom.check("b"));
+}
+
+#[test]
+fn counting_bloom_subtract() {
+    let mut a = CountingBloom::new_for_fp_rate(100, 0.001);
+    let mut b = CountingBloom::new_for_fp_rate(100, 0.001);
+    for i in 0..50u32 {
+        a.set(i);
+    }
+    for i in 0..40u32 {
+        b.set(i);
+    }
+    b.set(1000u32);
+    let difference = a.difference(&b).unwrap();
+    assert!((9.0..=11.0).contains(&difference));
+
+    a.subtract(&b).unwrap();
+    assert!((0..40u32).all(|i| !a.check(i)));
+    assert!((40..50u32).all(|i| a.check(i)));
+    assert!((9.0..=11.0).contains(&a.estimated_len()));
+
+    let other = CountingBloom::new(10, 5);
+    assert_eq!(a.subtract(&other), Err(BloomError::SizeMismatch));
+}
+
+#[test]
+fn counting_bloom_counter_width() {
+    for &(width, max) in [(CounterWidth::Four, 15), (CounterWidth::Eight, 255), (CounterWidth::Sixteen, 65535)].iter() {
+        let mut bloom = CountingBloom::new(1001, 10).with_counter_width(width);
+        assert_eq!(bloom.counter_width(), width);
+        for _ in 0..300 {
+            bloom.set("a");
+        }
+        bloom.set("b");
+        assert_eq!(bloom.count("a"), max.min(300));
+        assert!(bloom.unset("b"));
+        assert!(!bloom.check("b"));
+        assert!(bloom.unset("a"));
+        assert_eq!(bloom.count("a"), max.min(300) - (max > 300) as u16);
+        bloom.halve();
+        assert!(bloom.check("a"));
+    }
+    let compact = CountingBloom::new(100, 10).with_counter_width(CounterWidth::Four);
+    assert_eq!(compact.counters.bytes.len(), 50);
+    let mut other = CountingBloom::new(100, 10);
+    assert_eq!(other.subtract(&compact), Err(BloomError::SizeMismatch));
+}
+
+#[test]
+fn counting_bloom_try_set() {
+    let mut bloom = CountingBloom::new(1000, 10).with_counter_width(CounterWidth::Four)
+        .with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    for _ in 0..14 {
+        bloom.try_set("a").unwrap();
+    }
+    assert_eq!(bloom.try_set("a"), Err(BloomError::CounterOverflow));
+    assert_eq!(bloom.count("a"), 14);
+    assert_eq!(bloom.overflows(), 0);
+    for _ in 0..14 {
+        assert!(bloom.unset("a"));
+    }
+    assert!(!bloom.check("a"));
+
+    for _ in 0..16 {
+        bloom.set("a");
+    }
+    assert_eq!(bloom.overflows(), bloom.number_of_hash_functions() as u64);
+}
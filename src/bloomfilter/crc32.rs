@@ -0,0 +1,36 @@
+//! CRC-32 (IEEE 802.3, as used by zlib and PNG), to detect corrupted data.
+
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Update a checksum with more data. The checksum of empty data is 0.
+pub(crate) fn update(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+/// Return the checksum of data
+pub(crate) fn checksum(data: &[u8]) -> u32 {
+    update(0, data)
+}
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(checksum(b""), 0);
+    assert_eq!(checksum(b"123456789"), 0xcbf43926);
+    assert_eq!(update(checksum(b"1234"), b"56789"), 0xcbf43926);
+}
@@ -0,0 +1,209 @@
+//! Replication of the changes made to a filter.
+//!
+//! The writer calls `take_delta` to collect the words that were modified
+//! since the previous call, and the readers apply them with `apply_delta`,
+//! so that only the modified regions of the bitmap have to be transferred.
+//! Deltas are built out of the same journal as `drain_journal`: a word drained
+//! by one of them won't be returned by the other.
+//!
+//! Deltas can also be computed between two serialized snapshots of a filter
+//! with `BloomDelta::diff`, for example to back up or catch up with a filter
+//! that doesn't track its changes, and applied to a snapshot with
+//! `BloomDelta::apply_diff`.
+
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+use super::serialize::read_u64;
+use super::{Bloom, BloomError, BloomRef};
+
+/// Words modified in a filter since the previous call to `take_delta`,
+/// or between two snapshots of a filter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BloomDelta {
+    /// Number of bits of the filter the delta was taken from
+    pub bitmap_bits: u64,
+    /// Number of hash functions of the filter the delta was taken from
+    pub k_num: u32,
+    /// Number of items recorded in the filter the delta was taken from
+    pub approximate_len: u64,
+    /// Runs of consecutive modified words, as the index of the first word
+    /// of the run and the new values of the words, in increasing order
+    pub ranges: Vec<(usize, Vec<u64>)>,
+}
+
+impl BloomDelta {
+    /// Return true if the delta doesn't contain any change
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Compute the words that differ between two snapshots of a filter,
+    /// serialized with `Bloom::to_bytes`, so that applying the delta to the
+    /// old snapshot gives the new one.
+    /// Both snapshots must have the same parameters and hash functions.
+    pub fn diff(old: &[u8], new: &[u8]) -> io::Result<BloomDelta> {
+        let old = BloomRef::from_bytes(old)?;
+        let new = BloomRef::from_bytes(new)?;
+        if old.number_of_bits() != new.number_of_bits() || old.number_of_hash_functions() != new.number_of_hash_functions() {
+            return Err(BloomError::SizeMismatch.into());
+        }
+        if !old.hasher().is_compatible(new.hasher()) {
+            return Err(BloomError::IncompatibleSeeds.into());
+        }
+        let changed = old.bitmap().chunks_exact(8).zip(new.bitmap().chunks_exact(8)).enumerate()
+            .filter(|(_, (old_word, new_word))| old_word != new_word)
+            .map(|(index, (_, new_word))| (index, u64::from_le_bytes(new_word.try_into().expect("chunks are 8 bytes long"))));
+        Ok(BloomDelta {
+            bitmap_bits: new.number_of_bits(),
+            k_num: new.number_of_hash_functions(),
+            approximate_len: new.approximate_len() as u64,
+            ranges: ranges(changed),
+        })
+    }
+
+    /// Apply a delta computed with `diff` to the old snapshot, and return
+    /// the new one.
+    pub fn apply_diff(base: &[u8], diff: &BloomDelta) -> io::Result<Vec<u8>> {
+        let mut bloom = Bloom::from_bytes(base)?;
+        bloom.apply_delta(diff)?;
+        Ok(bloom.to_bytes())
+    }
+
+    /// Serialize the delta into a writer, as little-endian integers.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.bitmap_bits.to_le_bytes())?;
+        w.write_all(&self.k_num.to_le_bytes())?;
+        w.write_all(&self.approximate_len.to_le_bytes())?;
+        w.write_all(&(self.ranges.len() as u64).to_le_bytes())?;
+        for (start, words) in &self.ranges {
+            w.write_all(&(*start as u64).to_le_bytes())?;
+            w.write_all(&(words.len() as u64).to_le_bytes())?;
+            for word in words {
+                w.write_all(&word.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deserialize a delta previously serialized with `write_to`.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<BloomDelta> {
+        let bitmap_bits = read_u64(r)?;
+        let mut k_num = [0u8; 4];
+        r.read_exact(&mut k_num)?;
+        let approximate_len = read_u64(r)?;
+        let count = read_u64(r)?;
+        let mut ranges = Vec::new();
+        for _ in 0..count {
+            let start = read_u64(r)? as usize;
+            let len = read_u64(r)?;
+            let words = (0..len).map(|_| read_u64(r)).collect::<io::Result<Vec<u64>>>()?;
+            ranges.push((start, words));
+        }
+        Ok(BloomDelta {
+            bitmap_bits,
+            k_num: u32::from_le_bytes(k_num),
+            approximate_len,
+            ranges,
+        })
+    }
+}
+
+/// Group modified words, given in increasing order, into runs
+fn ranges<I>(entries: I) -> Vec<(usize, Vec<u64>)>
+    where I: IntoIterator<Item = (usize, u64)>
+{
+    let mut ranges: Vec<(usize, Vec<u64>)> = Vec::new();
+    for (index, word) in entries {
+        match ranges.last_mut() {
+            Some((start, words)) if *start + words.len() == index => words.push(word),
+            _ => ranges.push((index, vec![word])),
+        }
+    }
+    ranges
+}
+
+impl Bloom {
+    /// Return the words modified since the previous call to `take_delta`
+    /// (or `drain_journal`), and reset the tracking.
+    pub fn take_delta(&mut self) -> BloomDelta {
+        let mut entries = self.bitmap.drain();
+        entries.sort_unstable_by_key(|&(index, _)| index);
+        BloomDelta {
+            bitmap_bits: self.bitmap_bits,
+            k_num: self.k_num,
+            approximate_len: self.items,
+            ranges: ranges(entries),
+        }
+    }
+
+    /// Apply a delta taken from another filter with the same parameters.
+    /// The modified words are replaced with their values from the other filter,
+    /// and the number of items is replaced with the one of the other filter.
+    pub fn apply_delta(&mut self, delta: &BloomDelta) -> Result<(), BloomError> {
+        if delta.bitmap_bits != self.bitmap_bits || delta.k_num != self.k_num {
+            return Err(BloomError::SizeMismatch);
+        }
+        let len = self.bitmap.elems.len();
+        if delta.ranges.iter().any(|(start, words)| start.checked_add(words.len()).is_none_or(|end| end > len)) {
+            return Err(BloomError::CorruptData { reason: "delta range out of bounds" });
+        }
+        for (start, words) in &delta.ranges {
+            for (i, word) in words.iter().enumerate() {
+                self.bitmap.set_word(start + i, *word);
+            }
+        }
+        self.items = delta.approximate_len;
+        Ok(())
+    }
+}
+
+#[test]
+fn bloom_delta_replication() {
+    let mut writer = Bloom::new(1024, 100);
+    let mut reader = Bloom::from_bytes(&writer.to_bytes()).unwrap();
+    assert!(writer.take_delta().is_empty());
+
+    for i in 0..10u32 {
+        writer.set(i);
+    }
+    let delta = writer.take_delta();
+    assert!(!delta.is_empty());
+    assert!(delta.ranges.windows(2).all(|w| w[0].0 + w[0].1.len() < w[1].0));
+    assert!(writer.take_delta().is_empty());
+
+    let mut bytes = Vec::new();
+    delta.write_to(&mut bytes).unwrap();
+    let delta = BloomDelta::read_from(&mut &bytes[..]).unwrap();
+    reader.apply_delta(&delta).unwrap();
+    assert_eq!(reader.to_bytes(), writer.to_bytes());
+
+    let mut other = Bloom::new(512, 100);
+    assert_eq!(other.apply_delta(&delta), Err(BloomError::SizeMismatch));
+}
+
+#[test]
+fn bloom_delta_snapshot_diff() {
+    let mut bloom = Bloom::new(4096, 1000);
+    for i in 0..500u32 {
+        bloom.set(i);
+    }
+    let old = bloom.to_bytes();
+    for i in 500..520u32 {
+        bloom.set(i);
+    }
+    let new = bloom.to_bytes();
+
+    let diff = BloomDelta::diff(&old, &new).unwrap();
+    let changed: usize = diff.ranges.iter().map(|(_, words)| words.len()).sum();
+    assert!(changed > 0 && changed <= 20 * bloom.number_of_hash_functions() as usize);
+    assert_eq!(BloomDelta::apply_diff(&old, &diff).unwrap(), new);
+    assert!(BloomDelta::diff(&new, &new).unwrap().is_empty());
+
+    let other = Bloom::new(2048, 1000).to_bytes();
+    let err = BloomDelta::diff(&old, &other).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::SizeMismatch));
+    let keyed = Bloom::new(4096, 1000).with_sip_keys(super::SipKeys([(1, 2), (3, 4)])).to_bytes();
+    let err = BloomDelta::diff(&old, &keyed).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::IncompatibleSeeds));
+}
@@ -0,0 +1,68 @@
+//! Errors returned by fallible bloom filter operations.
+//!
+//! Readers of serialized filters return `io::Error`s, since they can also
+//! fail while reading. When the data itself is invalid, the error is of kind
+//! `InvalidData` and wraps a `BloomError`, that `BloomError::from_io_error`
+//! returns. Only malformed hex strings, given to `Bloom::from_hex`, are
+//! reported with a plain message.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Error returned when building or combining filters, or when reading data
+/// that doesn't describe a valid filter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BloomError {
+    /// The parameters don't describe a usable filter
+    InvalidParams,
+    /// The filters have different sizes or numbers of hash functions
+    SizeMismatch,
+    /// The filters don't use the same hash functions
+    IncompatibleSeeds,
+    /// The data is malformed
+    CorruptData { reason: &'static str },
+    /// The data was serialized with an unknown version of the format
+    UnsupportedVersion { version: u8 },
+    /// A counter of a counting filter would overflow
+    CounterOverflow,
+    /// The bitmap, of the given size in bytes, couldn't be allocated
+    AllocationFailed { bytes: usize },
+    /// The filter already holds the number of items it was designed for
+    CapacityExceeded,
+}
+
+impl BloomError {
+    /// Return the `BloomError` an I/O error was built from, if any
+    pub fn from_io_error(err: &io::Error) -> Option<&BloomError> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<BloomError>())
+    }
+
+    /// Return the I/O error reported when reading malformed data
+    pub(crate) fn corrupt(reason: &'static str) -> io::Error {
+        BloomError::CorruptData { reason }.into()
+    }
+}
+
+impl fmt::Display for BloomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            BloomError::InvalidParams => write!(f, "invalid bloom filter parameters"),
+            BloomError::SizeMismatch => write!(f, "bloom filter parameters don't match"),
+            BloomError::IncompatibleSeeds => write!(f, "bloom filters use different hash functions"),
+            BloomError::CorruptData { reason } => write!(f, "corrupt bloom filter data: {}", reason),
+            BloomError::UnsupportedVersion { version } => write!(f, "unsupported bloom filter format version {}", version),
+            BloomError::CounterOverflow => write!(f, "bloom filter counter would overflow"),
+            BloomError::AllocationFailed { bytes } => write!(f, "failed to allocate a {} bytes bloom filter bitmap", bytes),
+            BloomError::CapacityExceeded => write!(f, "bloom filter is full"),
+        }
+    }
+}
+
+impl Error for BloomError {}
+
+impl From<BloomError> for io::Error {
+    fn from(err: BloomError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
@@ -0,0 +1,116 @@
+//! Bloom filter whose items expire after a time to live.
+
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use super::Bloom;
+
+/// Bloom filter with "seen within the last ttl" semantics.
+///
+/// Time is split into `generations` slots of ttl / generations each, and
+/// every slot gets its own filter. Items are recorded in the filter of the
+/// current slot, and filters are recycled once their slot is older than the
+/// time to live. An item keeps matching for at least ttl after it was last
+/// recorded, and stops matching at most ttl + ttl / generations after that.
+pub struct ExpiringBloom {
+    filters: VecDeque<Bloom>,
+    slot: Duration,
+    current_start: Instant,
+}
+
+impl ExpiringBloom {
+    /// Create a new expiring bloom filter structure.
+    /// bitmap_size is the size in bytes of the bitmap of each generation,
+    /// items_count is an estimation of the maximum number of items recorded
+    /// during a single generation (ttl / generations).
+    pub fn new(bitmap_size: usize, items_count: usize, ttl: Duration, generations: u32) -> ExpiringBloom {
+        ExpiringBloom::new_at(bitmap_size, items_count, ttl, generations, Instant::now())
+    }
+
+    /// Create a new expiring bloom filter structure, whose first generation
+    /// starts at now.
+    pub fn new_at(bitmap_size: usize, items_count: usize, ttl: Duration, generations: u32, now: Instant) -> ExpiringBloom {
+        assert!(generations > 0 && ttl >= Duration::from_nanos(generations as u64));
+        ExpiringBloom {
+            filters: (0..=generations).map(|_| Bloom::new(bitmap_size, items_count)).collect(),
+            slot: ttl / generations,
+            current_start: now,
+        }
+    }
+
+    /// Record the presence of an item, now.
+    pub fn set<T>(&mut self, item: T)
+        where T: Hash
+    {
+        self.set_at(item, Instant::now())
+    }
+
+    /// Record the presence of an item, at a given time.
+    /// Times must not go backwards.
+    pub fn set_at<T>(&mut self, item: T, now: Instant)
+        where T: Hash
+    {
+        self.advance(now);
+        self.filters[0].set(item);
+    }
+
+    /// Check if an item was recorded within the time to live.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        self.check_at(item, Instant::now())
+    }
+
+    /// Check if an item was recorded within the time to live, as of a given time.
+    pub fn check_at<T>(&self, item: T, now: Instant) -> bool
+        where T: Hash
+    {
+        let elapsed = self.elapsed_slots(now);
+        if elapsed >= self.filters.len() {
+            return false;
+        }
+        let hashes = self.filters[0].hasher.hash_pair(&item);
+        self.filters.iter().take(self.filters.len() - elapsed).any(|filter| filter.check_hashes(hashes))
+    }
+
+    /// Recycle the filters of the generations that expired at a given time.
+    pub fn advance(&mut self, now: Instant) {
+        let elapsed = self.elapsed_slots(now);
+        for _ in 0..elapsed.min(self.filters.len()) {
+            let mut filter = self.filters.pop_back().expect("there is always at least one generation");
+            filter.clear();
+            self.filters.push_front(filter);
+        }
+        let nanos = self.slot.as_nanos() * elapsed as u128;
+        self.current_start += Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32);
+    }
+
+    fn elapsed_slots(&self, now: Instant) -> usize {
+        let elapsed = now.saturating_duration_since(self.current_start);
+        (elapsed.as_nanos() / self.slot.as_nanos()) as usize
+    }
+}
+
+#[test]
+fn expiring_bloom_ttl() {
+    let start = Instant::now();
+    let ttl = Duration::from_secs(60);
+    let mut bloom = ExpiringBloom::new_at(128, 10, ttl, 4, start);
+    bloom.set_at("a", start + Duration::from_secs(5));
+    bloom.set_at("b", start + Duration::from_secs(30));
+
+    assert!(bloom.check_at("a", start + Duration::from_secs(64)));
+    assert!(!bloom.check_at("a", start + Duration::from_secs(75)));
+    assert!(bloom.check_at("b", start + Duration::from_secs(75)));
+
+    bloom.advance(start + Duration::from_secs(75));
+    assert!(!bloom.check_at("a", start + Duration::from_secs(75)));
+    assert!(bloom.check_at("b", start + Duration::from_secs(89)));
+    assert!(!bloom.check_at("b", start + Duration::from_secs(105)));
+
+    bloom.set_at("c", start + Duration::from_secs(3600));
+    assert!(!bloom.check_at("b", start + Duration::from_secs(3600)));
+    assert!(bloom.check_at("c", start + Duration::from_secs(3600)));
+}
@@ -0,0 +1,254 @@
+//! HyperLogLog, estimating the number of distinct items of a set.
+//!
+//! Items are hashed with the same hash functions as bloom filters, so that
+//! both sketches can be configured once, for example with the same keys.
+//!
+//! Serialized sketches are, little-endian:
+//!
+//! * version: u8 (currently 1)
+//! * precision: u8
+//! * SipHash keys: 4 u64
+//! * hash algorithm: u8
+//! * registers: 2^precision 6-bit values, packed least significant bit first
+
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+
+use super::{Bloom, BloomError, BloomHasher, HashAlgorithm, SipKeys};
+
+const FORMAT_VERSION: u8 = 1;
+
+/// Smallest and largest precisions
+const MIN_PRECISION: u8 = 4;
+const MAX_PRECISION: u8 = 18;
+
+/// Number of bits of a serialized register
+const REGISTER_BITS: usize = 6;
+
+/// Sketch estimating the number of distinct items recorded into it
+#[derive(Clone)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+    precision: u8,
+    hasher: BloomHasher,
+}
+
+impl HyperLogLog {
+    /// Create a new sketch with 2^precision registers, precision being in [4, 18].
+    /// The standard error of estimates is about 1.04 / sqrt(2^precision).
+    pub fn new(precision: u8) -> HyperLogLog {
+        assert!((MIN_PRECISION..=MAX_PRECISION).contains(&precision));
+        HyperLogLog {
+            registers: vec![0u8; 1 << precision],
+            precision,
+            hasher: BloomHasher::new(),
+        }
+    }
+
+    /// Create a new sketch with the smallest precision giving a standard
+    /// error of at most std_error, in ]0.0, 1.0[
+    pub fn new_for_error(std_error: f64) -> HyperLogLog {
+        assert!(std_error > 0.0 && std_error < 1.0);
+        let precision = (1.04 / std_error).powi(2).log2().ceil();
+        HyperLogLog::new(precision.clamp(MIN_PRECISION as f64, MAX_PRECISION as f64) as u8)
+    }
+
+    /// Create a new sketch hashing items like a bloom filter does
+    pub fn for_bloom(bloom: &Bloom, precision: u8) -> HyperLogLog {
+        HyperLogLog {
+            hasher: bloom.hasher.clone(),
+            ..HyperLogLog::new(precision)
+        }
+    }
+
+    /// Use different SipHash keys, see `Bloom::with_sip_keys`.
+    pub fn with_sip_keys(self, sip_keys: SipKeys) -> HyperLogLog {
+        let algorithm = self.hasher.algorithm();
+        HyperLogLog {
+            hasher: BloomHasher::with_algorithm(sip_keys, algorithm),
+            ..self
+        }
+    }
+
+    /// Use different hash functions, see `Bloom::with_hash_algorithm`.
+    pub fn with_hash_algorithm(self, algorithm: HashAlgorithm) -> HyperLogLog {
+        let sip_keys = self.hasher.keys();
+        HyperLogLog {
+            hasher: BloomHasher::with_algorithm(sip_keys, algorithm),
+            ..self
+        }
+    }
+
+    /// Record an item.
+    pub fn add<T>(&mut self, item: T)
+        where T: Hash
+    {
+        let hash = self.hasher.hash_pair(&item)[0];
+        let index = (hash >> (64 - self.precision)) as usize;
+        let rank = ((hash << self.precision) | (1 << (self.precision - 1))).leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// Estimate the number of distinct items that were recorded
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|&r| f64::powi(2.0, -(r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            // Linear counting is more accurate for small cardinalities
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+
+    /// Record all the items of another sketch into this one.
+    /// Both sketches must have the same precision and hash functions.
+    pub fn merge(&mut self, other: &HyperLogLog) -> Result<(), BloomError> {
+        if self.precision != other.precision {
+            return Err(BloomError::SizeMismatch);
+        }
+        if !self.hasher.is_compatible(&other.hasher) {
+            return Err(BloomError::IncompatibleSeeds);
+        }
+        for (register, &other) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(other);
+        }
+        Ok(())
+    }
+
+    /// Remove all the items from the sketch.
+    pub fn clear(&mut self) {
+        self.registers.iter_mut().for_each(|register| *register = 0);
+    }
+
+    /// Return the precision of the sketch
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    /// Serialize the sketch into a writer.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&[FORMAT_VERSION, self.precision])?;
+        for (k0, k1) in self.hasher.keys().0.iter() {
+            w.write_all(&k0.to_le_bytes())?;
+            w.write_all(&k1.to_le_bytes())?;
+        }
+        w.write_all(&[self.hasher.algorithm().id()])?;
+        let mut packed = vec![0u8; (self.registers.len() * REGISTER_BITS).div_ceil(8)];
+        for (i, &register) in self.registers.iter().enumerate() {
+            let bit = i * REGISTER_BITS;
+            let value = (register as u16) << (bit % 8);
+            packed[bit / 8] |= value as u8;
+            if let Some(byte) = packed.get_mut(bit / 8 + 1) {
+                *byte |= (value >> 8) as u8;
+            }
+        }
+        w.write_all(&packed)
+    }
+
+    /// Deserialize a sketch previously serialized with `write_to`.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<HyperLogLog> {
+        let mut header = [0u8; 2];
+        r.read_exact(&mut header)?;
+        let [version, precision] = header;
+        if version != FORMAT_VERSION {
+            return Err(BloomError::UnsupportedVersion { version }.into());
+        }
+        if !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+            return Err(BloomError::InvalidParams.into());
+        }
+        let mut keys = [0u8; 33];
+        r.read_exact(&mut keys)?;
+        let mut sip_keys = SipKeys::default();
+        for (i, (k0, k1)) in sip_keys.0.iter_mut().enumerate() {
+            *k0 = u64::from_le_bytes(array8(&keys[i * 16..]));
+            *k1 = u64::from_le_bytes(array8(&keys[i * 16 + 8..]));
+        }
+        let algorithm = HashAlgorithm::from_id(keys[32]).ok_or_else(|| BloomError::corrupt("unsupported hash algorithm"))?;
+        let count = 1usize << precision;
+        let mut packed = vec![0u8; (count * REGISTER_BITS).div_ceil(8)];
+        r.read_exact(&mut packed)?;
+        let registers = (0..count).map(|i| {
+            let bit = i * REGISTER_BITS;
+            let lo = packed[bit / 8] as u16;
+            let hi = packed.get(bit / 8 + 1).map_or(0, |&byte| byte as u16);
+            (((hi << 8 | lo) >> (bit % 8)) & 0x3f) as u8
+        }).collect();
+        Ok(HyperLogLog {
+            registers,
+            precision,
+            hasher: BloomHasher::with_algorithm(sip_keys, algorithm),
+        })
+    }
+
+    /// Serialize the sketch into a vector of bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes).expect("writing to a vector cannot fail");
+        bytes
+    }
+
+    /// Deserialize a sketch previously serialized with `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<HyperLogLog> {
+        HyperLogLog::read_from(&mut &bytes[..])
+    }
+}
+
+fn array8(bytes: &[u8]) -> [u8; 8] {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(&bytes[..8]);
+    array
+}
+
+#[test]
+fn hyperloglog_estimate() {
+    let mut hll = HyperLogLog::new_for_error(0.01).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    assert_eq!(hll.precision(), 14);
+    assert_eq!(hll.estimate(), 0.0);
+    for i in 0..100000u32 {
+        hll.add(i);
+        hll.add(i);
+    }
+    let estimate = hll.estimate();
+    assert!((97000.0..103000.0).contains(&estimate));
+
+    let mut small = HyperLogLog::new(14).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    for i in 0..100u32 {
+        small.add(i);
+    }
+    assert!((97.0..103.0).contains(&small.estimate()));
+
+    let mut other = HyperLogLog::new(14).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    for i in 50000..150000u32 {
+        other.add(i);
+    }
+    hll.merge(&other).unwrap();
+    assert!((145000.0..155000.0).contains(&hll.estimate()));
+    assert_eq!(hll.merge(&HyperLogLog::new(12)), Err(BloomError::SizeMismatch));
+    assert_eq!(hll.merge(&HyperLogLog::new(14)), Err(BloomError::IncompatibleSeeds));
+}
+
+#[test]
+fn hyperloglog_serialize() {
+    let bloom = Bloom::new(16, 10).with_sip_keys(SipKeys([(5, 6), (7, 8)]));
+    let mut hll = HyperLogLog::for_bloom(&bloom, 10);
+    for i in 0..5000u32 {
+        hll.add(i);
+    }
+    let bytes = hll.to_bytes();
+    assert_eq!(bytes.len(), 2 + 33 + 1024 * 6 / 8);
+    let restored = HyperLogLog::from_bytes(&bytes).unwrap();
+    assert_eq!(restored.registers, hll.registers);
+    assert_eq!(restored.hasher.keys(), bloom.sip_keys());
+    assert!(HyperLogLog::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
@@ -0,0 +1,153 @@
+//! Bloom filter whose items can only be queried by holders of a secret key.
+//!
+//! Items are first turned into tags with SipHash-1-3-128 keyed with a secret
+//! key, and only the tags are recorded into a regular filter. That filter can
+//! be shared with third parties: without the secret key, they can't compute
+//! the tag of an item, and so can't check for any item offline, even though
+//! the structural SipHash keys are stored along with the filter.
+
+use std::fmt;
+use std::hash::Hash;
+
+use siphasher::sip128::{Hasher128, SipHasher13};
+
+use super::{Bloom, SipKeys, StableHasher};
+
+/// Secret key items are tagged with before being recorded
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SecretKey(pub u64, pub u64);
+
+impl SecretKey {
+    /// Generate a random key
+    #[cfg(feature = "rand")]
+    pub fn random() -> SecretKey {
+        SecretKey(rand::random(), rand::random())
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+/// Bloom filter recording keyed tags of items rather than items
+pub struct KeyedBloom {
+    bloom: Bloom,
+    key: SecretKey,
+}
+
+impl KeyedBloom {
+    /// Create a new keyed bloom filter structure.
+    /// bitmap_size is the size in bytes (not bits) that will be allocated in memory
+    /// items_count is an estimation of the maximum number of items to store.
+    pub fn new(bitmap_size: usize, items_count: usize, key: SecretKey) -> KeyedBloom {
+        KeyedBloom::from_bloom(Bloom::new(bitmap_size, items_count), key)
+    }
+
+    /// Create a new keyed bloom filter structure.
+    /// items_count is an estimation of the maximum number of items to store.
+    /// fp_p is the wanted rate of false positives, in ]0.0, 1.0[
+    pub fn new_for_fp_rate(items_count: usize, fp_p: f64, key: SecretKey) -> KeyedBloom {
+        KeyedBloom::from_bloom(Bloom::new_for_fp_rate(items_count, fp_p), key)
+    }
+
+    /// Query or update a filter of tags, for example one that was shared and
+    /// deserialized, with the key it was built with.
+    pub fn from_bloom(bloom: Bloom, key: SecretKey) -> KeyedBloom {
+        KeyedBloom { bloom, key }
+    }
+
+    /// Use different structural SipHash keys, see `Bloom::with_sip_keys`.
+    pub fn with_sip_keys(self, sip_keys: SipKeys) -> KeyedBloom {
+        KeyedBloom {
+            bloom: self.bloom.with_sip_keys(sip_keys),
+            ..self
+        }
+    }
+
+    /// Record the presence of an item.
+    pub fn set<T>(&mut self, item: T)
+        where T: Hash
+    {
+        let tag = self.tag(&item);
+        self.bloom.set(tag);
+    }
+
+    /// Record the presence of an item, and return true if it was definitely
+    /// new, see `Bloom::insert`.
+    pub fn insert<T>(&mut self, item: T) -> bool
+        where T: Hash
+    {
+        let tag = self.tag(&item);
+        self.bloom.insert(tag)
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        self.bloom.check(self.tag(&item))
+    }
+
+    /// Return the filter of tags, that can be shared without the key
+    pub fn bloom(&self) -> &Bloom {
+        &self.bloom
+    }
+
+    /// Return the filter of tags, and forget the key
+    pub fn into_bloom(self) -> Bloom {
+        self.bloom
+    }
+
+    /// Return an empty filter with the same parameters and structural keys,
+    /// using another secret key. During a rotation, items can be recorded
+    /// into both filters until the old one is retired.
+    pub fn with_new_key(&self, key: SecretKey) -> KeyedBloom {
+        KeyedBloom::from_bloom(self.bloom.empty_like(), key)
+    }
+
+    /// Rotate the secret key, by recording all the items again into a filter
+    /// with the same parameters and another secret key.
+    pub fn rekey<I>(&self, key: SecretKey, items: I) -> KeyedBloom
+        where I: IntoIterator,
+              I::Item: Hash
+    {
+        let mut keyed = self.with_new_key(key);
+        for item in items {
+            keyed.set(item);
+        }
+        keyed
+    }
+
+    fn tag<T>(&self, item: &T) -> u128
+        where T: Hash
+    {
+        let mut sip = SipHasher13::new_with_keys(self.key.0, self.key.1);
+        item.hash(&mut StableHasher(&mut sip));
+        sip.finish128().as_u128()
+    }
+}
+
+#[test]
+fn keyed_bloom_check() {
+    let key = SecretKey(1, 2);
+    let mut keyed = KeyedBloom::new_for_fp_rate(100, 0.001, key);
+    for i in 0..100u32 {
+        keyed.set(i);
+    }
+    assert!((0..100u32).all(|i| keyed.check(i)));
+    assert!((0..100u32).filter(|i| keyed.bloom().check(i)).count() < 5);
+
+    let shared = Bloom::from_bytes(&keyed.bloom().to_bytes()).unwrap();
+    let wrong_key = KeyedBloom::from_bloom(shared, SecretKey(1, 3));
+    assert!((0..100u32).filter(|&i| wrong_key.check(i)).count() < 5);
+    let restored = KeyedBloom::from_bloom(wrong_key.into_bloom(), key);
+    assert!((0..100u32).all(|i| restored.check(i)));
+
+    let rotated = keyed.rekey(SecretKey(3, 4), 0..100u32);
+    assert_eq!(rotated.bloom().number_of_bits(), keyed.bloom().number_of_bits());
+    assert!((0..100u32).all(|i| rotated.check(i)));
+    assert!(rotated.bloom().bitmap.elems != keyed.bloom().bitmap.elems);
+}
@@ -3,22 +3,106 @@
 //! Bloom filter for Rust
 //!
 //! This is a simple but fast Bloom filter implementation, that requires only
-//! 2 hash functions, generated with SipHash-2-4 using randomized keys.
+//! 2 hash functions, generated with SipHash-2-4.
+//!
+//! Filters use fixed, all-zero SipHash keys by default, so that they can be
+//! rebuilt from their parts. Keys can be set explicitly with `with_sip_keys`,
+//! or generated randomly with `SipKeys::random` when the `rand` feature
+//! (enabled by default) is on.
+//!
+//! With the `serde` feature, filters can be embedded in JSON and other
+//! serde formats, see the `serde_base64` module. With the `proto` feature,
+//! they can be converted to and from protocol buffers messages, see the
+//! `proto` module. With the `rkyv` feature, they can be archived and queried
+//! in place, see the `archive` module. With the `bytes` feature, serialized
+//! filters held in `bytes::Bytes` buffers can be queried without copying
+//! them, see `BloomBytes`. With the `arc-swap` feature, `SwappableBloom`
+//! publishes rebuilt filters to concurrent readers.
+//!
+//! The `xxhash` and `wyhash` features add faster, non-cryptographic hash
+//! functions, see `HashAlgorithm`.
 //!
 
 #![crate_name="bloomfilter"]
 #![crate_type = "rlib"]
 #![warn(non_camel_case_types, non_upper_case_globals, unused_qualifications)]
 
-extern crate rand;
-
 use std::cmp;
+use std::convert::TryFrom;
 use std::f64;
-use std::hash::{Hash, Hasher, SipHasher};
-use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use siphasher::sip::SipHasher24;
+use siphasher::sip128::{Hasher128, SipHasher13};
+use std::collections::{HashSet, TryReserveError, VecDeque};
+
+mod admission;
+mod age_partitioned;
+mod approximate;
+#[cfg(feature = "rkyv")]
+pub mod archive;
+mod atomic;
+pub mod cassandra;
+mod cascade;
+mod convert;
+mod counting;
+mod crc32;
+mod delta;
+mod error;
+mod expiring;
+mod hyperloglog;
+mod keyed;
+mod merge;
+mod observer;
+mod paged;
+mod prefix;
+#[cfg(feature = "proto")]
+pub mod proto;
+mod ribbon;
+pub mod rocksdb;
+mod router;
+mod segmented;
+#[cfg(feature = "serde")]
+pub mod serde_base64;
+mod serialize;
+mod sharded;
+mod shared;
+#[cfg(feature = "bytes")]
+mod shared_bytes;
+mod stats;
+#[cfg(feature = "arc-swap")]
+mod swappable;
+mod view;
+mod wal;
+mod windows;
+pub mod wire;
 
-#[cfg(test)]
-use rand::Rng;
+pub use admission::AdmissionFilter;
+pub use age_partitioned::AgePartitionedBloom;
+pub use approximate::ApproximateSet;
+pub use atomic::AtomicBloom;
+pub use cascade::FilterCascade;
+pub use counting::{CounterWidth, CountingBloom};
+pub use delta::BloomDelta;
+pub use error::BloomError;
+pub use expiring::ExpiringBloom;
+pub use hyperloglog::HyperLogLog;
+pub use keyed::{KeyedBloom, SecretKey};
+pub use observer::{BloomCounters, BloomObserver, FpAlert, ObservedBloom};
+pub use paged::PagedBloom;
+pub use prefix::{CappedPrefix, FixedPrefix, PrefixBloom, PrefixExtractor};
+pub use ribbon::RibbonFilter;
+pub use router::ShardedRouter;
+pub use segmented::SegmentedBloom;
+pub use sharded::ShardedBloom;
+pub use shared::SharedBloom;
+#[cfg(feature = "bytes")]
+pub use shared_bytes::BloomBytes;
+pub use stats::BloomStats;
+#[cfg(feature = "arc-swap")]
+pub use swappable::SwappableBloom;
+pub use view::BloomRef;
+pub use wal::WalBloom;
 
 struct BitVecJournal {
     elems: Vec<u64>,
@@ -26,18 +110,30 @@ struct BitVecJournal {
 }
 
 impl BitVecJournal {
-    // size in bytes
+    // size in bits
     pub fn new(size: usize) -> BitVecJournal {
-        let extra = if size % 8 > 0  { 1 } else { 0 };
         BitVecJournal {
-            elems: vec![0u64; size / 8 + extra],
+            elems: vec![0u64; size.div_ceil(64)],
             journal: HashSet::new(),
         }
     }
 
+    // size in bits; the words are zeroed up front rather than on first use
+    pub fn try_new(size: usize) -> Result<BitVecJournal, TryReserveError> {
+        let words = size.div_ceil(64);
+        let mut elems = Vec::new();
+        elems.try_reserve_exact(words)?;
+        elems.resize(words, 0u64);
+        Ok(BitVecJournal::from_vec(elems))
+    }
+
     pub fn from_parts(parts: &[u64]) -> BitVecJournal {
+        BitVecJournal::from_vec(parts.to_vec())
+    }
+
+    pub fn from_vec(elems: Vec<u64>) -> BitVecJournal {
         BitVecJournal {
-            elems: parts.to_vec(),
+            elems,
             journal: HashSet::new(),
         }
     }
@@ -46,8 +142,24 @@ impl BitVecJournal {
     pub fn set(&mut self, index: usize) {
         let e_index = index / 64;
         let bit_index = index % 64;
-        let val = self.elems.get_mut(e_index).unwrap();
-        *val |= 1u64 << bit_index;
+        self.elems[e_index] |= 1u64 << bit_index;
+        self.journal.insert(e_index);
+    }
+
+    /// Set a bit without bounds checking.
+    ///
+    /// # Safety
+    ///
+    /// index must be lower than 64 * elems.len()
+    pub unsafe fn set_unchecked(&mut self, index: usize) {
+        let e_index = index / 64;
+        debug_assert!(e_index < self.elems.len());
+        *self.elems.get_unchecked_mut(e_index) |= 1u64 << (index % 64);
+        self.journal.insert(e_index);
+    }
+
+    pub fn set_word(&mut self, e_index: usize, value: u64) {
+        self.elems[e_index] = value;
         self.journal.insert(e_index);
     }
 
@@ -58,22 +170,415 @@ impl BitVecJournal {
         self.elems[e_index] & (1 << bit_index) != 0
     }
 
+    /// Get a bit without bounds checking.
+    ///
+    /// # Safety
+    ///
+    /// index must be lower than 64 * elems.len()
+    pub unsafe fn get_unchecked(&self, index: usize) -> bool {
+        let e_index = index / 64;
+        debug_assert!(e_index < self.elems.len());
+        *self.elems.get_unchecked(e_index) & (1 << (index % 64)) != 0
+    }
+
+    /// Hint the processor to load the word holding a bit into the cache.
+    /// This is only a hint: it never faults, and does nothing on
+    /// architectures without a stable prefetch instruction.
+    pub fn prefetch(&self, index: usize) {
+        let ptr = self.elems.as_ptr().wrapping_add(index / 64);
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: prefetching doesn't dereference the pointer, and sse is
+        // always available on x86_64.
+        unsafe {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            _mm_prefetch(ptr as *const i8, _MM_HINT_T0);
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = ptr;
+    }
+
+    /// Touch one word of every memory page, so that the pages are mapped
+    /// before they are needed.
+    pub fn prefault(&mut self) {
+        // 4 KiB pages; larger pages are touched several times
+        for elem in self.elems.iter_mut().step_by(4096 / 8) {
+            *elem = std::hint::black_box(*elem);
+        }
+    }
+
+    /// Reserve journal entries for setting bits in at most words words
+    pub fn reserve(&mut self, words: usize) {
+        let additional = words.min(self.elems.len()).saturating_sub(self.journal.len());
+        self.journal.reserve(additional);
+    }
+
+    pub fn clear(&mut self) {
+        for (e_index, val) in self.elems.iter_mut().enumerate() {
+            if *val != 0 {
+                *val = 0;
+                self.journal.insert(e_index);
+            }
+        }
+    }
+
     pub fn drain(&mut self) -> Vec<(usize, u64)> {
         let journal = self.journal.drain().collect::<Vec<usize>>();
         journal.iter().map(|idx| (*idx, self.elems[*idx])).collect::<Vec<(usize, u64)>>()
     }
 
+    pub fn count_ones(&self) -> u64 {
+        self.elems.iter().fold(0u64, |acc, e| acc + e.count_ones() as u64)
+    }
+
     pub fn how_full(&self) -> f64 {
-        self.elems.iter().fold(0u64, |acc, e| acc + e.count_ones() as u64) as f64 / (self.elems.len() * 64) as f64
+        self.count_ones() as f64 / (self.elems.len() * 64) as f64
+    }
+}
+
+/// Keys (k0, k1) of the two SipHash-2-4 functions bit offsets are derived from.
+/// The default keys are all zero, which is what filters were always built with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SipKeys(pub [(u64, u64); 2]);
+
+impl SipKeys {
+    /// Generate random keys
+    #[cfg(feature = "rand")]
+    pub fn random() -> SipKeys {
+        SipKeys([(rand::random(), rand::random()), (rand::random(), rand::random())])
+    }
+}
+
+/// Hash functions the bit offsets of items are derived from.
+/// Some of them depend on cargo features, so the set of variants is open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum HashAlgorithm {
+    /// Two SipHash-2-4 functions, one for each of the keys
+    #[default]
+    SipHash24,
+    /// A single SipHash-1-3 function with a 128-bit output, keyed with the
+    /// first of the keys. Both halves of the output are independent, which
+    /// is better for very large filters, since offsets are derived from them.
+    SipHash13x128,
+    /// Two XXH64 functions, seeded with the k0 of each of the keys.
+    /// Much faster than SipHash, but not designed to resist items chosen
+    /// to collide, even with secret keys. Requires the `xxhash` feature.
+    #[cfg(feature = "xxhash")]
+    Xxh64,
+    /// A single XXH3 function with a 128-bit output, seeded with the first k0.
+    /// Requires the `xxhash` feature.
+    #[cfg(feature = "xxhash")]
+    Xxh3x128,
+    /// Two wyhash (version 1) functions, seeded with the k0 of each of the
+    /// keys. Requires the `wyhash` feature.
+    #[cfg(feature = "wyhash")]
+    WyHash,
+}
+// aHash is deliberately not offered: its output depends on the version of
+// the crate and on the CPU features, so serialized filters couldn't be read
+// back reliably.
+
+impl HashAlgorithm {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            HashAlgorithm::SipHash24 => 0,
+            HashAlgorithm::SipHash13x128 => 1,
+            #[cfg(feature = "xxhash")]
+            HashAlgorithm::Xxh64 => 2,
+            #[cfg(feature = "xxhash")]
+            HashAlgorithm::Xxh3x128 => 3,
+            #[cfg(feature = "wyhash")]
+            HashAlgorithm::WyHash => 4,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<HashAlgorithm> {
+        match id {
+            0 => Some(HashAlgorithm::SipHash24),
+            1 => Some(HashAlgorithm::SipHash13x128),
+            #[cfg(feature = "xxhash")]
+            2 => Some(HashAlgorithm::Xxh64),
+            #[cfg(feature = "xxhash")]
+            3 => Some(HashAlgorithm::Xxh3x128),
+            #[cfg(feature = "wyhash")]
+            4 => Some(HashAlgorithm::WyHash),
+            _ => None,
+        }
+    }
+}
+
+/// The hash functions all the bit offsets are derived from
+#[derive(Clone)]
+pub(crate) struct BloomHasher {
+    keys: SipKeys,
+    algorithm: HashAlgorithm,
+    sips: [SipHasher24; 2],
+    sip128: SipHasher13,
+}
+
+impl BloomHasher {
+    pub fn new() -> BloomHasher {
+        BloomHasher::from_keys(SipKeys::default())
+    }
+
+    pub fn from_keys(keys: SipKeys) -> BloomHasher {
+        BloomHasher::with_algorithm(keys, HashAlgorithm::default())
+    }
+
+    pub fn with_algorithm(keys: SipKeys, algorithm: HashAlgorithm) -> BloomHasher {
+        let [(k0, k1), (k2, k3)] = keys.0;
+        BloomHasher {
+            keys,
+            algorithm,
+            sips: [BloomHasher::sip_new(k0, k1), BloomHasher::sip_new(k2, k3)],
+            sip128: SipHasher13::new_with_keys(k0, k1),
+        }
+    }
+
+    pub fn keys(&self) -> SipKeys {
+        self.keys
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// Return the k_num bit offsets of an item in a bitmap of bitmap_bits bits
+    pub fn bit_offsets<T>(&self, item: &T, k_num: u32, bitmap_bits: u64) -> BitOffsets
+        where T: Hash
+    {
+        BloomHasher::bit_offsets_for(self.hash_pair(item), k_num, bitmap_bits)
+    }
+
+    /// Return the k_num bit offsets derived from a pair of hashes
+    pub fn bit_offsets_for(hashes: [u64; 2], k_num: u32, bitmap_bits: u64) -> BitOffsets {
+        BitOffsets {
+            hashes,
+            k_i: 0,
+            k_num,
+            bitmap_bits,
+        }
+    }
+
+    pub fn hash_pair<T>(&self, item: &T) -> [u64; 2]
+        where T: Hash
+    {
+        self.hash_input(&HashedItem(item))
+    }
+
+    /// Hash raw bytes, without going through the `Hash` trait: the bytes are
+    /// the whole message hashed by each function, with no length prefix, so
+    /// the hashes can be computed by any implementation of the functions.
+    pub fn hash_bytes(&self, bytes: &[u8]) -> [u64; 2] {
+        self.hash_input(bytes)
+    }
+
+    fn hash_input<I>(&self, input: &I) -> [u64; 2]
+        where I: HashInput + ?Sized
+    {
+        match self.algorithm {
+            HashAlgorithm::SipHash24 => {
+                let mut hashes = [0u64; 2];
+                for (hash, sip) in hashes.iter_mut().zip(self.sips.iter()) {
+                    let sip = &mut sip.clone();
+                    input.feed(sip);
+                    *hash = sip.finish();
+                }
+                hashes
+            }
+            HashAlgorithm::SipHash13x128 => {
+                let sip = &mut self.sip128.clone();
+                input.feed(sip);
+                let hash = sip.finish128();
+                [hash.h1, hash.h2]
+            }
+            #[cfg(feature = "xxhash")]
+            HashAlgorithm::Xxh64 => {
+                let [(k0, _), (k2, _)] = self.keys.0;
+                [k0, k2].map(|seed| {
+                    let mut xxh = xxhash_rust::xxh64::Xxh64::new(seed);
+                    input.feed(&mut xxh);
+                    xxh.finish()
+                })
+            }
+            #[cfg(feature = "xxhash")]
+            HashAlgorithm::Xxh3x128 => {
+                let mut xxh = xxhash_rust::xxh3::Xxh3::with_seed(self.keys.0[0].0);
+                input.feed(&mut xxh);
+                let hash = xxh.digest128();
+                [hash as u64, (hash >> 64) as u64]
+            }
+            #[cfg(feature = "wyhash")]
+            HashAlgorithm::WyHash => {
+                let [(k0, _), (k2, _)] = self.keys.0;
+                [k0, k2].map(|seed| {
+                    let mut wyhash = wyhash::WyHash::with_seed(seed);
+                    input.feed(&mut wyhash);
+                    wyhash.finish()
+                })
+            }
+        }
+    }
+
+    /// Return true if both hashers map items to the same hashes
+    pub fn is_compatible(&self, other: &BloomHasher) -> bool {
+        self.keys == other.keys && self.algorithm == other.algorithm
+    }
+
+    fn sip_new(k0: u64, k1: u64) -> SipHasher24 {
+        SipHasher24::new_with_keys(k0, k1)
+    }
+}
+
+/// Data the hash functions are computed over
+trait HashInput {
+    fn feed<H: Hasher>(&self, hasher: &mut H);
+}
+
+/// Item hashed through its `Hash` implementation
+struct HashedItem<'a, T: ?Sized>(&'a T);
+
+impl<T: Hash + ?Sized> HashInput for HashedItem<'_, T> {
+    fn feed<H: Hasher>(&self, hasher: &mut H) {
+        self.0.hash(&mut StableHasher(hasher));
+    }
+}
+
+impl HashInput for [u8] {
+    fn feed<H: Hasher>(&self, hasher: &mut H) {
+        hasher.write(self);
     }
 }
 
+/// Hasher adapter making the hashes of items independent of the platform.
+/// Hashers usually hash integers in native order, and `usize`, that slices
+/// and arrays are prefixed with, in native size. Integers are hashed here as
+/// their little-endian bytes, and `usize` as a 64-bit integer, which is what
+/// 64-bit little-endian platforms, where filters were built so far, always did.
+pub(crate) struct StableHasher<'a, H: Hasher>(pub &'a mut H);
+
+impl<H: Hasher> Hasher for StableHasher<'_, H> {
+    fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes)
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.0.write_u8(i)
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.0.write(&i.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.0.write(&i.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0.write(&i.to_le_bytes())
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.0.write(&i.to_le_bytes())
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128)
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_usize(i as usize)
+    }
+}
+
+/// Iterator over the bit offsets of an item.
+/// The first two offsets are the hashes themselves, the following ones are
+/// derived from them using double hashing.
+pub(crate) struct BitOffsets {
+    hashes: [u64; 2],
+    k_i: u32,
+    k_num: u32,
+    bitmap_bits: u64,
+}
+
+impl Iterator for BitOffsets {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.k_i >= self.k_num {
+            return None;
+        }
+        let k_i = self.k_i;
+        self.k_i += 1;
+        Some(BitOffsets::nth(self.hashes, k_i, self.bitmap_bits))
+    }
+}
+
+impl BitOffsets {
+    /// Return the offset given by the k_i-th hash function
+    pub fn nth(hashes: [u64; 2], k_i: u32, bitmap_bits: u64) -> usize {
+        let hash = if k_i < 2 {
+            hashes[k_i as usize]
+        } else {
+            hashes[0].wrapping_add((k_i as u64).wrapping_mul(hashes[1]) % 0xffffffffffffffc5)
+        };
+        (hash % bitmap_bits) as usize
+    }
+}
+
+/// Estimate the number of items recorded in a filter of bitmap_bits bits
+/// using k_num hash functions, given the number of bits that are set
+pub(crate) fn estimate_items(bits_set: u64, bitmap_bits: u64, k_num: u32) -> u64 {
+    let m = bitmap_bits as f64;
+    let x = cmp::min(bits_set, bitmap_bits) as f64;
+    if x >= m {
+        return bitmap_bits;
+    }
+    (-m / k_num as f64 * f64::ln(1.0 - x / m)).round() as u64
+}
+
+/// Number of items for which k_num hash functions are optimal in a filter of
+/// bitmap_bits bits, used as the capacity of filters not built for a number of items
+pub(crate) fn implied_capacity(bitmap_bits: u64, k_num: u32) -> u64 {
+    let capacity = (bitmap_bits as f64 * f64::consts::LN_2 / k_num as f64).round() as u64;
+    cmp::max(capacity, 1)
+}
+
+/// Check that bitmap_bits bits are addressable on this platform, and held
+/// by a bitmap of the given number of words: the unchecked bitmap accessors
+/// rely on it for all the bit offsets.
+pub(crate) fn bitmap_fits(bitmap_bits: u64, words: usize) -> bool {
+    usize::try_from(bitmap_bits).is_ok() && words as u64 >= bitmap_bits.div_ceil(64)
+}
+
+/// Largest number of hash functions of a filter. Filters are never built
+/// with more, and serialized filters using more are rejected: every check
+/// computes that many bit offsets, and more functions would barely lower the
+/// rate of false positives.
+pub const MAX_K_NUM: u32 = 64;
+
+/// Number of items `check_many` hashes ahead of the one being tested
+pub const DEFAULT_PREFETCH_LOOKAHEAD: usize = 8;
+
 /// Bloom filter structure
 pub struct Bloom {
     bitmap: BitVecJournal,
     bitmap_bits: u64,
     k_num: u32,
-    sips: [SipHasher; 2],
+    hasher: BloomHasher,
+    // Number of items that changed at least one bit when recorded,
+    // or an estimation for filters restored from their bitmap
+    items: u64,
+    // Number of items the filter was designed for
+    capacity: u64,
 }
 
 impl Bloom {
@@ -85,12 +590,92 @@ impl Bloom {
         let bitmap_bits = (bitmap_size as u64) * 8u64;
         let k_num = Bloom::optimal_k_num(bitmap_bits, items_count);
         let bitmap = BitVecJournal::new(bitmap_bits as usize);
-        let sips = [Bloom::sip_new(), Bloom::sip_new()];
         Bloom {
-            bitmap: bitmap,
-            bitmap_bits: bitmap_bits,
-            k_num: k_num,
-            sips: sips,
+            capacity: items_count as u64,
+            ..Bloom::from_bitmap(bitmap, bitmap_bits, k_num)
+        }
+    }
+
+    /// Create a new bloom filter structure, like `new`, but return an error
+    /// instead of aborting the process if the bitmap can't be allocated, or
+    /// instead of panicking if the parameters are invalid.
+    /// The bitmap is written to while it is cleared, so the memory it uses
+    /// is committed before the filter is returned.
+    pub fn try_new(bitmap_size: usize, items_count: usize) -> Result<Bloom, BloomError> {
+        if bitmap_size == 0 || items_count == 0 {
+            return Err(BloomError::InvalidParams);
+        }
+        let bitmap_bits = (bitmap_size as u64).checked_mul(8)
+            .filter(|&bits| usize::try_from(bits).is_ok())
+            .ok_or(BloomError::InvalidParams)?;
+        let k_num = Bloom::optimal_k_num(bitmap_bits, items_count);
+        let bitmap = BitVecJournal::try_new(bitmap_bits as usize)
+            .map_err(|_| BloomError::AllocationFailed { bytes: bitmap_size })?;
+        Ok(Bloom {
+            capacity: items_count as u64,
+            ..Bloom::from_bitmap(bitmap, bitmap_bits, k_num)
+        })
+    }
+
+    /// Create a new bloom filter structure for a rate of false positives,
+    /// like `new_for_fp_rate`, but return an error if the bitmap can't be
+    /// allocated, see `try_new`.
+    pub fn try_new_for_fp_rate(items_count: usize, fp_p: f64) -> Result<Bloom, BloomError> {
+        if items_count == 0 || !(fp_p > 0.0 && fp_p < 1.0) {
+            return Err(BloomError::InvalidParams);
+        }
+        Bloom::try_new(Bloom::compute_bitmap_size(items_count, fp_p), items_count)
+    }
+
+    /// Create a new bloom filter structure, with a fixed number of hash functions.
+    /// bitmap_size is the size in bytes (not bits) that will be allocated in memory
+    /// k_num is the number of hash functions, instead of the optimal one for
+    /// an expected number of items, at most `MAX_K_NUM`.
+    pub fn new_with_k(bitmap_size: usize, k_num: u32) -> Bloom {
+        assert!(bitmap_size > 0 && k_num > 0);
+        let bitmap_bits = (bitmap_size as u64) * 8u64;
+        let bitmap = BitVecJournal::new(bitmap_bits as usize);
+        Bloom::from_bitmap(bitmap, bitmap_bits, k_num)
+    }
+
+    /// Use different SipHash keys, for example random ones.
+    /// This must be called on an empty filter, or when restoring a filter
+    /// with the keys it was built with: items recorded with other keys
+    /// can't be found any more.
+    pub fn with_sip_keys(self, sip_keys: SipKeys) -> Bloom {
+        let algorithm = self.hasher.algorithm();
+        Bloom {
+            hasher: BloomHasher::with_algorithm(sip_keys, algorithm),
+            ..self
+        }
+    }
+
+    /// Use different hash functions, for example 128-bit ones for very large
+    /// filters. Like `with_sip_keys`, this must be called on an empty filter,
+    /// or when restoring a filter.
+    pub fn with_hash_algorithm(self, algorithm: HashAlgorithm) -> Bloom {
+        let sip_keys = self.hasher.keys();
+        Bloom {
+            hasher: BloomHasher::with_algorithm(sip_keys, algorithm),
+            ..self
+        }
+    }
+
+    /// Return the hash functions used by the filter
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hasher.algorithm()
+    }
+
+    /// Return the SipHash keys of the filter
+    pub fn sip_keys(&self) -> SipKeys {
+        self.hasher.keys()
+    }
+
+    /// Create a new bloom filter structure from planned parameters.
+    pub fn from_params(params: &BloomParams) -> Bloom {
+        Bloom {
+            capacity: params.items_count as u64,
+            ..Bloom::new_with_k(params.bitmap_size, params.k_num)
         }
     }
 
@@ -98,12 +683,33 @@ impl Bloom {
         let bitmap_size = parts.len()*8;
         let bitmap_bits = (bitmap_size as u64) * 8u64;
         let bitmap = BitVecJournal::from_parts(parts);
-        let sips = [Bloom::sip_new(), Bloom::sip_new()];
+        let mut bloom = Bloom::from_bitmap(bitmap, bitmap_bits, k_num);
+        bloom.items = bloom.estimate_items();
+        bloom
+    }
+
+    fn from_bitmap(bitmap: BitVecJournal, bitmap_bits: u64, k_num: u32) -> Bloom {
+        assert!(bitmap_fits(bitmap_bits, bitmap.elems.len()), "bitmap too small for {} bits", bitmap_bits);
+        assert!(k_num <= MAX_K_NUM, "more than MAX_K_NUM hash functions");
+        Bloom {
+            bitmap,
+            bitmap_bits,
+            k_num,
+            hasher: BloomHasher::new(),
+            items: 0,
+            capacity: implied_capacity(bitmap_bits, k_num),
+        }
+    }
+
+    /// Return an empty filter with the same parameters and hash functions
+    pub(crate) fn empty_like(&self) -> Bloom {
         Bloom {
-            bitmap: bitmap,
-            bitmap_bits: bitmap_bits,
-            k_num: k_num,
-            sips: sips,
+            bitmap: BitVecJournal::new(self.bitmap_bits as usize),
+            bitmap_bits: self.bitmap_bits,
+            k_num: self.k_num,
+            hasher: self.hasher.clone(),
+            items: 0,
+            capacity: self.capacity,
         }
     }
 
@@ -115,6 +721,31 @@ impl Bloom {
         Bloom::new(bitmap_size, items_count)
     }
 
+    /// Create a new bloom filter structure, using at most max_bytes bytes for
+    /// its bitmap.
+    /// items_count is an estimation of the maximum number of items to store.
+    /// fp_p is the wanted rate of false positives, in ]0.0, 1.0[
+    /// If fp_p can be reached within the budget, a bitmap just large enough
+    /// to reach it is used, otherwise the whole budget is. The returned report tells
+    /// which parameters were picked, and the expected rate of false positives.
+    pub fn new_bounded(items_count: usize, fp_p: f64, max_bytes: usize) -> Result<(Bloom, PlanReport), BloomError> {
+        if items_count == 0 || max_bytes == 0 || !(fp_p > 0.0 && fp_p < 1.0) {
+            return Err(BloomError::InvalidParams);
+        }
+        let mut params = BloomParams::plan_for_memory(items_count, cmp::min(Bloom::compute_bitmap_size(items_count, fp_p), max_bytes));
+        // The number of hash functions is rounded, so the recommended size
+        // can fall slightly short of the target.
+        while params.fp_rate > fp_p && params.bitmap_size < max_bytes {
+            let bitmap_size = cmp::min(params.bitmap_size + cmp::max(params.bitmap_size / 1024, 1), max_bytes);
+            params = BloomParams::plan_for_memory(items_count, bitmap_size);
+        }
+        let report = PlanReport {
+            params,
+            fp_target: fp_p,
+        };
+        Ok((Bloom::from_params(&params), report))
+    }
+
     /// Compute a recommended bitmap size for items_count items
     /// and a fp_p rate of false positives.
     /// fp_p obviously has to be within the ]0.0, 1.0[ range.
@@ -127,29 +758,222 @@ impl Bloom {
     }
 
     /// Record the presence of an item.
+    /// Items can be passed by reference, including unsized ones such as
+    /// `&str` and `&[u8]`: a reference is hashed like the value it points
+    /// to, and a `String` or a `Vec<u8>` like its contents, so an item
+    /// recorded in one of these forms can be checked in any other.
     pub fn set<T>(&mut self, item: T)
         where T: Hash
     {
-        let mut hashes = [0u64, 0u64];
-        for k_i in 0..self.k_num {
-            let bit_offset = (self.bloom_hash(&mut hashes, &item, k_i) % self.bitmap_bits) as usize;
-            self.bitmap.set(bit_offset);
-        }
+        self.check_and_set(item);
     }
 
     /// Check if an item is present in the set.
     /// There can be false positives, but no false negatives.
+    /// As with `set`, items can be passed by reference.
     pub fn check<T>(&self, item: T) -> bool
         where T: Hash
     {
-        let mut hashes = [0u64, 0u64];
-        for k_i in 0..self.k_num {
-            let bit_offset = (self.bloom_hash(&mut hashes, &item, k_i) % self.bitmap_bits) as usize;
-            if !self.bitmap.get(bit_offset) {
-                return false;
-            }
+        self.check_hashes(self.hasher.hash_pair(&item))
+    }
+
+    /// Record the presence of an item, and return true if it was definitely
+    /// new, that is if recording it changed at least one bit.
+    /// This is the opposite of what `check_and_set` returns.
+    pub fn insert<T>(&mut self, item: T) -> bool
+        where T: Hash
+    {
+        !self.check_and_set(item)
+    }
+
+    /// Record the presence of an item in the set,
+    /// and return the previous state of this item.
+    pub fn check_and_set<T>(&mut self, item: T) -> bool
+        where T: Hash
+    {
+        let hashes = self.hasher.hash_pair(&item);
+        self.check_and_set_hashes(hashes)
+    }
+
+    /// Record the presence of an item, unless it is new and the filter already
+    /// holds `capacity` items, so that the rate of false positives never
+    /// exceeds `target_fp_rate`. The filter is then left unchanged.
+    /// Return true if the item was definitely new, as `insert` does.
+    /// Items that may already be present are accepted even when the filter
+    /// is full, since recording them changes nothing.
+    pub fn try_insert<T>(&mut self, item: T) -> Result<bool, BloomError>
+        where T: Hash
+    {
+        let hashes = self.hasher.hash_pair(&item);
+        if self.check_hashes(hashes) {
+            return Ok(false);
+        }
+        if self.items >= self.capacity {
+            return Err(BloomError::CapacityExceeded);
+        }
+        Ok(!self.check_and_set_hashes(hashes))
+    }
+
+    /// Record the presence of an item, unless the filter is full, see `try_insert`.
+    pub fn try_set<T>(&mut self, item: T) -> Result<(), BloomError>
+        where T: Hash
+    {
+        self.try_insert(item).map(|_| ())
+    }
+
+    /// Record the presence of a byte string, hashed as is rather than through
+    /// the `Hash` trait, see `check_bytes`.
+    pub fn set_bytes(&mut self, bytes: &[u8]) {
+        self.insert_bytes(bytes);
+    }
+
+    /// Record the presence of a byte string, and return true if it was
+    /// definitely new, as `insert` does.
+    pub fn insert_bytes(&mut self, bytes: &[u8]) -> bool {
+        let hashes = self.hasher.hash_bytes(bytes);
+        !self.check_and_set_hashes(hashes)
+    }
+
+    /// Check if a byte string recorded with `set_bytes` is present in the set.
+    /// The bytes are hashed directly with the SipHash functions of the filter,
+    /// without the length prefix the `Hash` trait adds, so that filters can
+    /// be built and queried by implementations in other languages. Byte
+    /// strings recorded with `set` are not found by `check_bytes`, and
+    /// conversely.
+    pub fn check_bytes(&self, bytes: &[u8]) -> bool {
+        self.check_hashes(self.hasher.hash_bytes(bytes))
+    }
+
+    /// Record the presence of many items, and return how many of them were
+    /// definitely new, as `insert` does.
+    /// Room for tracking the changes is reserved up front. When loading a lot
+    /// of items into a new, large filter, call `prefault` first.
+    pub fn insert_all<I>(&mut self, items: I) -> usize
+        where I: IntoIterator,
+              I::Item: Hash
+    {
+        let items = items.into_iter();
+        self.bitmap.reserve(items.size_hint().0.saturating_mul(self.k_num as usize));
+        items.filter(|item| self.insert(item)).count()
+    }
+
+    /// Map the memory of the bitmap before it is used.
+    /// The bitmap of a new filter is allocated lazily by the operating system,
+    /// so a bulk load into it alternates between hashing and page faults;
+    /// touching all the pages first moves the faults out of the loop.
+    pub fn prefault(&mut self) {
+        self.bitmap.prefault();
+    }
+
+    /// Check if all the items may be present in the set.
+    /// Stops at the first item that is definitely absent.
+    pub fn contains_all<I>(&self, items: I) -> bool
+        where I: IntoIterator,
+              I::Item: Hash
+    {
+        items.into_iter().all(|item| self.check(item))
+    }
+
+    /// Check if at least one of the items may be present in the set.
+    /// Stops at the first item that may be present.
+    pub fn contains_any<I>(&self, items: I) -> bool
+        where I: IntoIterator,
+              I::Item: Hash
+    {
+        items.into_iter().any(|item| self.check(item))
+    }
+
+    /// Remove all the items from the set.
+    pub fn clear(&mut self) {
+        self.bitmap.clear();
+        self.items = 0;
+    }
+
+    /// Return the number of items recorded with `set`, `insert` or
+    /// `check_and_set` that changed at least one bit.
+    /// Items that were false positives when recorded are not counted.
+    /// For filters rebuilt from their parts, or merged with other filters,
+    /// the number is estimated from the number of bits set.
+    pub fn approximate_len(&self) -> usize {
+        self.items as usize
+    }
+
+    /// Return the number of items the filter was designed for: the items_count
+    /// given at construction, or for filters built from a number of hash
+    /// functions, the number of items for which that number is optimal.
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    /// Return the expected rate of false positives once `capacity` items
+    /// have been recorded.
+    pub fn target_fp_rate(&self) -> f64 {
+        BloomParams::expected_fp_rate(self.bitmap_bits, self.k_num, self.capacity as usize)
+    }
+
+    /// Estimate how many more items can be recorded before the rate of false
+    /// positives exceeds `target_fp_rate`.
+    /// This is derived from the current fill ratio, and not from the number of
+    /// recorded items, so it remains meaningful for merged or restored filters.
+    /// It reaches 0 when the filter should be rotated.
+    pub fn estimated_remaining(&self) -> usize {
+        // The target rate is reached at a fill ratio of 1 - e^(-k*capacity/m),
+        // which is where the estimated number of items reaches the capacity.
+        self.capacity.saturating_sub(self.estimate_items()) as usize
+    }
+
+    /// Estimate the number of items from the number of bits set
+    pub(crate) fn estimate_items(&self) -> u64 {
+        estimate_items(self.bitmap.count_ones(), self.bitmap_bits, self.k_num)
+    }
+
+    /// Check a batch of items, returning the result of `check` for each of them.
+    /// The bits of the following items are prefetched while the current one is
+    /// tested, see `check_many_with_lookahead`.
+    pub fn check_many<T>(&self, items: &[T]) -> Vec<bool>
+        where T: Hash
+    {
+        self.check_many_with_lookahead(items, DEFAULT_PREFETCH_LOOKAHEAD)
+    }
+
+    /// Check a batch of items, like `check_many`, hashing items lookahead
+    /// positions ahead of the one being tested and prefetching their bits,
+    /// so that memory latency is hidden on filters larger than the cache.
+    /// A lookahead of 0 disables prefetching.
+    pub fn check_many_with_lookahead<T>(&self, items: &[T], lookahead: usize) -> Vec<bool>
+        where T: Hash
+    {
+        if lookahead == 0 {
+            return items.iter().map(|item| self.check(item)).collect();
+        }
+        let mut hashes = items.iter().map(|item| {
+            let hashes = self.hasher.hash_pair(item);
+            self.prefetch_hashes(hashes);
+            hashes
+        });
+        let mut pending: VecDeque<[u64; 2]> = hashes.by_ref().take(lookahead).collect();
+        let mut results = Vec::with_capacity(items.len());
+        for next in hashes {
+            pending.push_back(next);
+            results.push(self.check_hashes(pending.pop_front().unwrap()));
+        }
+        results.extend(pending.into_iter().map(|hashes| self.check_hashes(hashes)));
+        results
+    }
+
+    /// Check a batch of items, writing the results into a bitmask rather
+    /// than a vector: bit i % 64 of bitmask[i / 64] is set if items[i] may be
+    /// present. Words covered by items are overwritten; bitmask needs room
+    /// for at least items.len() bits.
+    pub fn check_many_bitmask<T>(&self, items: &[T], bitmask: &mut [u64])
+        where T: Hash
+    {
+        assert!(bitmask.len() * 64 >= items.len());
+        for (word, chunk) in bitmask.iter_mut().zip(items.chunks(64)) {
+            *word = chunk.iter().enumerate().fold(0u64, |acc, (i, item)| {
+                if self.check(item) { acc | (1u64 << i) } else { acc }
+            });
         }
-        true
     }
 
     /// Return the number of bits in the filter
@@ -166,25 +990,42 @@ impl Bloom {
         let m = bitmap_bits as f64;
         let n = items_count as f64;
         let k_num = (m / n * f64::ln(2.0f64)).ceil() as u32;
-        cmp::max(k_num, 1)
+        k_num.clamp(1, MAX_K_NUM)
     }
 
-    fn bloom_hash<T>(&self, hashes: &mut [u64; 2], item: &T, k_i: u32) -> u64
-        where T: Hash
-    {
-        if k_i < 2 {
-            let sip = &mut self.sips[k_i as usize].clone();
-            item.hash(sip);
-            let hash = sip.finish();
-            hashes[k_i as usize] = hash;
-            hash
-        } else {
-            hashes[0].wrapping_add((k_i as u64).wrapping_mul(hashes[1]) % 0xffffffffffffffc5)
+    /// Record the presence of an item given its hash pair,
+    /// and return the previous state of this item.
+    pub(crate) fn check_and_set_hashes(&mut self, hashes: [u64; 2]) -> bool {
+        let mut found = true;
+        for bit_offset in BloomHasher::bit_offsets_for(hashes, self.k_num, self.bitmap_bits) {
+            // SAFETY: offsets are reduced modulo bitmap_bits, and the bitmap
+            // always has room for bitmap_bits bits.
+            unsafe {
+                if !self.bitmap.get_unchecked(bit_offset) {
+                    found = false;
+                    self.bitmap.set_unchecked(bit_offset);
+                }
+            }
+        }
+        if !found {
+            self.items += 1;
         }
+        found
+    }
+
+    /// Check if an item is present in the set, given its hash pair
+    pub(crate) fn check_hashes(&self, hashes: [u64; 2]) -> bool {
+        BloomHasher::bit_offsets_for(hashes, self.k_num, self.bitmap_bits).all(|bit_offset| {
+            // SAFETY: see check_and_set_hashes
+            unsafe { self.bitmap.get_unchecked(bit_offset) }
+        })
     }
 
-    fn sip_new() -> SipHasher {
-        SipHasher::new()
+    /// Prefetch the bits of an item, given its hash pair
+    fn prefetch_hashes(&self, hashes: [u64; 2]) {
+        for bit_offset in BloomHasher::bit_offsets_for(hashes, self.k_num, self.bitmap_bits) {
+            self.bitmap.prefetch(bit_offset);
+        }
     }
 
     pub fn drain_journal(&mut self) -> BloomJournal {
@@ -197,6 +1038,24 @@ impl Bloom {
     pub fn how_full(&self) -> f64 {
         self.bitmap.how_full()
     }
+
+    /// Return true if no bits are set, that is if no items were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.bitmap.elems.iter().all(|&word| word == 0)
+    }
+
+    /// Return the probability that an item that was never recorded is
+    /// reported as present, given the current fill ratio.
+    pub fn current_fp_rate(&self) -> f64 {
+        let fill = self.bitmap.count_ones() as f64 / self.bitmap_bits as f64;
+        fill.powi(self.k_num as i32)
+    }
+
+    /// Return true if the filter is so full that its current rate of false
+    /// positives exceeds fp_threshold, and it should be rotated or rebuilt.
+    pub fn is_saturated(&self, fp_threshold: f64) -> bool {
+        self.current_fp_rate() > fp_threshold
+    }
 }
 
 pub struct BloomJournal {
@@ -204,20 +1063,121 @@ pub struct BloomJournal {
     pub entries: Vec<(usize, u64)>,
 }
 
+/// Parameters chosen for a bloom filter, along with their expected false
+/// positive rate, so that the tradeoffs can be inspected before building one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomParams {
+    /// Number of items the filter was planned for
+    pub items_count: usize,
+    /// Size of the bitmap, in bytes
+    pub bitmap_size: usize,
+    /// Size of the bitmap, in bits (m)
+    pub bitmap_bits: u64,
+    /// Number of hash functions (k)
+    pub k_num: u32,
+    /// Expected false positive rate once items_count items have been stored
+    pub fp_rate: f64,
+}
+
+impl BloomParams {
+    /// Plan a filter for items_count items and a fp_p rate of false positives.
+    /// fp_p has to be within the ]0.0, 1.0[ range.
+    pub fn plan(items_count: usize, fp_p: f64) -> BloomParams {
+        let bitmap_size = Bloom::compute_bitmap_size(items_count, fp_p);
+        BloomParams::plan_for_memory(items_count, bitmap_size)
+    }
+
+    /// Plan a filter for items_count items using a bitmap of max_bytes bytes.
+    pub fn plan_for_memory(items_count: usize, max_bytes: usize) -> BloomParams {
+        assert!(max_bytes > 0 && items_count > 0);
+        let bitmap_bits = (max_bytes as u64) * 8u64;
+        let k_num = Bloom::optimal_k_num(bitmap_bits, items_count);
+        BloomParams {
+            items_count,
+            bitmap_size: max_bytes,
+            bitmap_bits,
+            k_num,
+            fp_rate: BloomParams::expected_fp_rate(bitmap_bits, k_num, items_count),
+        }
+    }
+
+    /// Override the number of hash functions, for example to interoperate
+    /// with filters built elsewhere, and update the expected false positive rate.
+    /// k_num must be at most `MAX_K_NUM`.
+    pub fn with_k_num(self, k_num: u32) -> BloomParams {
+        assert!(k_num > 0 && k_num <= MAX_K_NUM);
+        BloomParams {
+            k_num,
+            fp_rate: BloomParams::expected_fp_rate(self.bitmap_bits, k_num, self.items_count),
+            ..self
+        }
+    }
+
+    /// Expected false positive rate of a filter of bitmap_bits bits using
+    /// k_num hash functions, after items_count items have been stored.
+    pub fn expected_fp_rate(bitmap_bits: u64, k_num: u32, items_count: usize) -> f64 {
+        let m = bitmap_bits as f64;
+        let k = k_num as f64;
+        let n = items_count as f64;
+        (1.0 - f64::exp(-k * n / m)).powf(k)
+    }
+}
+
+/// Outcome of planning a filter under a memory budget
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlanReport {
+    /// Parameters that were picked
+    pub params: BloomParams,
+    /// Rate of false positives that was asked for
+    pub fp_target: f64,
+}
+
+impl PlanReport {
+    /// Return true if the expected rate of false positives reaches the target
+    pub fn meets_target(&self) -> bool {
+        self.params.fp_rate <= self.fp_target
+    }
+}
+
 #[test]
 fn bloom_test_set() {
     let mut bloom = Bloom::new(10, 80);
-    let key: &Vec<u8> = &rand::thread_rng().gen_iter::<u8>().take(16).collect();
-    assert!(bloom.check(key) == false);
-    bloom.set(&key);
-    assert!(bloom.check(key.clone()) == true);
+    let key: &Vec<u8> = &rand::random_iter::<u8>().take(16).collect();
+    assert!(!bloom.check(key));
+    bloom.set(key);
+    assert!(bloom.check(key.clone()));
+}
+
+#[test]
+fn bloom_unsized_items() {
+    let mut bloom = Bloom::new_for_fp_rate(100, 0.001);
+    bloom.set(String::from("foo"));
+    bloom.set(vec![1u8, 2, 3]);
+    bloom.set(&b"bar"[..]);
+    let bytes: &[u8] = &[0, 1, 2, 3, 4];
+    assert!(bloom.check("foo"));
+    assert!(bloom.check(&bytes[1..4]));
+    assert!(bloom.check(b"bar".to_vec()));
+    assert!(!bloom.check(&bytes[..3]));
+    assert_eq!(bloom.check_many(&["foo", "baz"]), [true, false]);
+}
+
+#[test]
+fn bloom_unaligned_bitmap() {
+    let mut bloom = Bloom::new(13, 10);
+    assert_eq!(bloom.bitmap.elems.len(), 2);
+    for i in 0..1000u32 {
+        bloom.set(i);
+    }
+    assert!((0..1000u32).all(|i| bloom.check(i)));
+    assert_eq!(bloom.bitmap.elems[1] >> (104 - 64), 0);
 }
 
 #[test]
 fn bloom_journalling() {
     let initial = vec![0u64; 8];
     let mut bloom = Bloom::from_parts(&initial, 3);
-    bloom.set(&vec![5u8, 4]);
+    bloom.set(vec![5u8, 4]);
     let drain = bloom.drain_journal();
 
     assert_eq!(2, drain.entries.len())
@@ -228,9 +1188,332 @@ fn bloom_journalling() {
 fn bloom_howfull() {
     let initial = vec![0u64; 8];
     let mut bloom = Bloom::from_parts(&initial, 3);
-    bloom.set(&vec![5u8, 4]);
+    bloom.set(vec![5u8, 4]);
 
     let full = bloom.how_full();
     // 2/8/64 = 0.00390625
-    assert!(full >= 0.003f64 && full <= 0.004f64);
-}
\ No newline at end of file
+    assert!((0.003f64..=0.004f64).contains(&full));
+}
+
+#[test]
+fn bloom_check_many_bitmask() {
+    let mut bloom = Bloom::new_for_fp_rate(100, 0.001);
+    let items: Vec<u32> = (0..70).collect();
+    for item in items.iter().filter(|item| *item % 3 == 0) {
+        bloom.set(item);
+    }
+    let mut bitmask = [!0u64; 2];
+    bloom.check_many_bitmask(&items, &mut bitmask);
+    let results = bloom.check_many(&items);
+    for (i, result) in results.iter().enumerate() {
+        assert!(i % 3 != 0 || *result);
+        assert_eq!(*result, bitmask[i / 64] & (1u64 << (i % 64)) != 0);
+    }
+    assert_eq!(bitmask[1] >> 6, 0);
+}
+
+#[test]
+fn bloom_check_many_lookahead() {
+    let mut bloom = Bloom::new_for_fp_rate(1000, 0.01);
+    for i in (0..1000u32).step_by(2) {
+        bloom.set(i);
+    }
+    let items: Vec<u32> = (0..1000).collect();
+    let expected: Vec<bool> = items.iter().map(|item| bloom.check(item)).collect();
+    for lookahead in [0, 1, 8, 999, 1000, 5000] {
+        assert_eq!(bloom.check_many_with_lookahead(&items, lookahead), expected);
+    }
+    assert_eq!(bloom.check_many(&items[..3]), &expected[..3]);
+    assert!(bloom.check_many::<u32>(&[]).is_empty());
+}
+
+#[test]
+fn bloom_contains_all_any() {
+    let mut bloom = Bloom::new_for_fp_rate(100, 0.001);
+    bloom.set("quick");
+    bloom.set("fox");
+    assert!(bloom.contains_all(&["quick", "fox"]));
+    assert!(!bloom.contains_all(&["quick", "brown", "fox"]));
+    assert!(bloom.contains_any(&["brown", "fox"]));
+    assert!(!bloom.contains_any(&["brown", "lazy"]));
+    assert!(bloom.contains_all(Vec::<&str>::new()));
+}
+
+#[test]
+fn bloom_new_with_k() {
+    let mut bloom = Bloom::new_with_k(128, 3);
+    assert_eq!(bloom.number_of_hash_functions(), 3);
+    assert_eq!(bloom.number_of_bits(), 1024);
+    bloom.set(1u32);
+    assert!(bloom.check(1u32));
+
+    let params = BloomParams::plan(100, 0.01);
+    let fixed = params.with_k_num(2);
+    assert_eq!(fixed.bitmap_bits, params.bitmap_bits);
+    assert!(fixed.fp_rate > params.fp_rate);
+    let bloom = Bloom::from_params(&fixed);
+    assert_eq!(bloom.number_of_hash_functions(), 2);
+    assert_eq!(bloom.number_of_bits(), params.bitmap_bits);
+}
+
+#[test]
+fn bloom_new_bounded() {
+    let (bloom, report) = Bloom::new_bounded(1000, 0.01, 1 << 20).unwrap();
+    assert!(report.meets_target());
+    assert!(report.params.bitmap_size >= Bloom::compute_bitmap_size(1000, 0.01));
+    assert!(report.params.bitmap_size < 2 * Bloom::compute_bitmap_size(1000, 0.01));
+    assert_eq!(bloom.number_of_bits(), report.params.bitmap_bits);
+
+    let (bloom, report) = Bloom::new_bounded(1000, 0.01, 600).unwrap();
+    assert!(!report.meets_target());
+    assert_eq!(report.params.bitmap_size, 600);
+    assert_eq!(bloom.number_of_hash_functions(), report.params.k_num);
+
+    assert_eq!(Bloom::new_bounded(1000, 1.5, 600).err(), Some(BloomError::InvalidParams));
+}
+
+#[test]
+fn bloom_try_insert() {
+    let mut bloom = Bloom::new_for_fp_rate(100, 0.01).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    let mut i = 0u32;
+    while bloom.approximate_len() < bloom.capacity() {
+        bloom.try_set(i).unwrap();
+        i += 1;
+    }
+    assert!(bloom.current_fp_rate() < 0.02);
+    let bytes = bloom.to_bytes();
+    let absent = (i..).find(|&i| !bloom.check(i)).unwrap();
+    assert_eq!(bloom.try_insert(absent), Err(BloomError::CapacityExceeded));
+    assert_eq!(bloom.try_insert(5u32), Ok(false));
+    assert_eq!(bloom.to_bytes(), bytes);
+    bloom.clear();
+    assert_eq!(bloom.try_insert(absent), Ok(true));
+}
+
+#[test]
+fn bloom_try_new() {
+    let mut bloom = Bloom::try_new(1024, 100).unwrap();
+    assert_eq!(bloom.to_bytes(), Bloom::new(1024, 100).to_bytes());
+    bloom.set("key");
+    assert!(bloom.check("key"));
+    assert_eq!(Bloom::try_new_for_fp_rate(100, 0.01).unwrap().number_of_bits(), Bloom::new_for_fp_rate(100, 0.01).number_of_bits());
+
+    let bytes = usize::MAX / 16;
+    assert_eq!(Bloom::try_new(bytes, 100).err(), Some(BloomError::AllocationFailed { bytes }));
+    assert_eq!(Bloom::try_new(0, 100).err(), Some(BloomError::InvalidParams));
+    assert_eq!(Bloom::try_new_for_fp_rate(100, 0.0).err(), Some(BloomError::InvalidParams));
+}
+
+#[test]
+#[should_panic(expected = "bitmap too small")]
+fn bloom_bitmap_holds_all_bits() {
+    assert!(bitmap_fits(64, 1) && bitmap_fits(65, 2) && !bitmap_fits(65, 1));
+    Bloom::from_bitmap(BitVecJournal::new(64), 65, 1);
+}
+
+#[test]
+fn bloom_sip_keys() {
+    let keys = SipKeys([(1, 2), (3, 4)]);
+    let mut bloom = Bloom::new(128, 10).with_sip_keys(keys);
+    assert_eq!(bloom.sip_keys(), keys);
+    bloom.set("key");
+    assert!(bloom.check("key"));
+
+    let mut unkeyed = Bloom::new(128, 10);
+    unkeyed.set("key");
+    assert_ne!(unkeyed.bitmap.elems, bloom.bitmap.elems);
+    assert_eq!(unkeyed.union(&bloom), Err(BloomError::IncompatibleSeeds));
+
+    let restored = Bloom::from_parts(&bloom.bitmap.elems, bloom.number_of_hash_functions()).with_sip_keys(keys);
+    assert!(restored.check("key"));
+}
+
+#[test]
+fn bloom_hasher_layout() {
+    // Values computed with std::hash::SipHasher, which filters used to be
+    // built with, so that existing filters keep working.
+    assert_eq!(BloomHasher::new().hash_pair(&"key"), [7716504569872002776, 7716504569872002776]);
+    assert_eq!(BloomHasher::from_keys(SipKeys([(1, 2), (3, 4)])).hash_pair(&42u64), [6030862880566504635, 15208501083358976157]);
+}
+
+#[test]
+fn bloom_bytes_items() {
+    // SipHash-2-4 reference vector for the 00..0f key and an empty message
+    let keys = SipKeys([(0x0706050403020100, 0x0f0e0d0c0b0a0908); 2]);
+    let hasher = BloomHasher::from_keys(keys);
+    assert_eq!(hasher.hash_bytes(b""), [0x726fdb47dd0e0e31; 2]);
+    assert_ne!(hasher.hash_bytes(b"key"), hasher.hash_pair(&&b"key"[..]));
+
+    for algorithm in [HashAlgorithm::SipHash24, HashAlgorithm::SipHash13x128] {
+        let mut bloom = Bloom::new_for_fp_rate(100, 0.001).with_hash_algorithm(algorithm);
+        assert!(bloom.insert_bytes(b"quick"));
+        bloom.set_bytes(b"fox");
+        assert!(!bloom.insert_bytes(b"fox"));
+        assert!(bloom.check_bytes(b"quick") && bloom.check_bytes(b"fox"));
+        assert!(!bloom.check_bytes(b"brown"));
+        assert!(!bloom.check(&b"quick"[..]));
+        assert_eq!(bloom.approximate_len(), 2);
+    }
+}
+
+#[test]
+fn bloom_stable_hashes() {
+    fn hash<T: Hash>(item: &T) -> u64 {
+        let mut sip = SipHasher24::new();
+        item.hash(&mut StableHasher(&mut sip));
+        sip.finish()
+    }
+    fn raw(bytes: &[u8]) -> u64 {
+        let mut sip = SipHasher24::new();
+        sip.write(bytes);
+        sip.finish()
+    }
+    assert_eq!(hash(&0x0102u16), raw(&[2, 1]));
+    assert_eq!(hash(&0x01020304u32), raw(&[4, 3, 2, 1]));
+    assert_eq!(hash(&-2i128), raw(&[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]));
+    assert_eq!(hash(&vec![7u8, 8]), raw(&[2, 0, 0, 0, 0, 0, 0, 0, 7, 8]));
+    assert_eq!(hash(&[7u8, 8]), hash(&vec![7u8, 8]));
+    assert_eq!(hash(&"ab"), raw(&[b'a', b'b', 0xff]));
+}
+
+#[cfg(feature = "xxhash")]
+#[test]
+fn bloom_xxhash() {
+    let keys = SipKeys([(1, 2), (3, 4)]);
+    let hasher = BloomHasher::with_algorithm(keys, HashAlgorithm::Xxh64);
+    assert_eq!(hasher.hash_bytes(b"key"), [xxhash_rust::xxh64::xxh64(b"key", 1), xxhash_rust::xxh64::xxh64(b"key", 3)]);
+    assert_eq!(hasher.hash_pair(&7u32), [xxhash_rust::xxh64::xxh64(&[7, 0, 0, 0], 1), xxhash_rust::xxh64::xxh64(&[7, 0, 0, 0], 3)]);
+    let hash = xxhash_rust::xxh3::xxh3_128_with_seed(b"key", 1);
+    let hasher = BloomHasher::with_algorithm(keys, HashAlgorithm::Xxh3x128);
+    assert_eq!(hasher.hash_bytes(b"key"), [hash as u64, (hash >> 64) as u64]);
+
+    for algorithm in [HashAlgorithm::Xxh64, HashAlgorithm::Xxh3x128] {
+        let mut bloom = Bloom::new(1024, 100).with_sip_keys(keys).with_hash_algorithm(algorithm);
+        bloom.insert_all(0..100u32);
+        let restored = Bloom::from_bytes(&bloom.to_bytes()).unwrap();
+        assert_eq!(restored.hash_algorithm(), algorithm);
+        assert!((0..100u32).all(|i| restored.check(i)));
+    }
+}
+
+#[cfg(feature = "wyhash")]
+#[test]
+fn bloom_wyhash() {
+    let keys = SipKeys([(1, 2), (3, 4)]);
+    let hasher = BloomHasher::with_algorithm(keys, HashAlgorithm::WyHash);
+    let mut wyhash = wyhash::WyHash::with_seed(3);
+    wyhash.write(b"key");
+    assert_eq!(hasher.hash_bytes(b"key")[1], wyhash.finish());
+
+    let mut bloom = Bloom::new(1024, 100).with_sip_keys(keys).with_hash_algorithm(HashAlgorithm::WyHash);
+    bloom.insert_all(0..100u32);
+    let restored = Bloom::from_bytes(&bloom.to_bytes()).unwrap();
+    assert_eq!(restored.hash_algorithm(), HashAlgorithm::WyHash);
+    assert!((0..100u32).all(|i| restored.check(i)));
+}
+
+#[test]
+fn bloom_hash_algorithm() {
+    let mut bloom = Bloom::new(1024, 100).with_hash_algorithm(HashAlgorithm::SipHash13x128);
+    assert_eq!(bloom.hash_algorithm(), HashAlgorithm::SipHash13x128);
+    for i in 0..100u32 {
+        bloom.set(i);
+    }
+    assert!((0..100u32).all(|i| bloom.check(i)));
+    let hashes = bloom.hasher.hash_pair(&1u32);
+    assert_ne!(hashes[0], hashes[1]);
+
+    let mut other = Bloom::new(1024, 100);
+    assert_eq!(other.union(&bloom), Err(BloomError::IncompatibleSeeds));
+}
+
+#[test]
+fn bloom_insert() {
+    let mut bloom = Bloom::new_for_fp_rate(100, 0.001);
+    let new = (0..100u32).filter(|i| bloom.insert(i)).count();
+    assert_eq!(new, 100);
+    assert!(!bloom.insert(7u32));
+    assert!(bloom.check_and_set(7u32));
+}
+
+#[test]
+fn bloom_insert_all() {
+    let mut bloom = Bloom::new_for_fp_rate(1000, 0.001).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    bloom.prefault();
+    assert!(bloom.is_empty());
+    let inserted = bloom.insert_all(0..500u32);
+    assert!((495..=500).contains(&inserted));
+    assert!((245..=250).contains(&bloom.insert_all(250..750u32)));
+    assert!(bloom.approximate_len() > inserted);
+    assert!((0..750u32).all(|i| bloom.check(i)));
+}
+
+#[test]
+fn bloom_approximate_len() {
+    let keys = SipKeys([(1, 2), (3, 4)]);
+    let mut bloom = Bloom::new_with_k(1024, 10).with_sip_keys(keys);
+    for i in 0..500u32 {
+        bloom.set(i);
+        bloom.set(i);
+    }
+    assert!((495..=500).contains(&bloom.approximate_len()));
+
+    let restored = Bloom::from_parts(&bloom.bitmap.elems, bloom.number_of_hash_functions()).with_sip_keys(keys);
+    assert!((475..=525).contains(&restored.approximate_len()));
+
+    bloom.clear();
+    assert_eq!(bloom.approximate_len(), 0);
+}
+
+#[test]
+fn bloom_is_empty_saturated() {
+    let mut bloom = Bloom::new_for_fp_rate(100, 0.01);
+    assert!(bloom.is_empty());
+    assert!(!bloom.is_saturated(0.01));
+    for i in 0..100u32 {
+        bloom.set(i);
+    }
+    assert!(!bloom.is_empty());
+    assert!(!bloom.is_saturated(0.02));
+    for i in 100..1000u32 {
+        bloom.set(i);
+    }
+    assert!(bloom.is_saturated(0.5));
+    bloom.clear();
+    assert!(bloom.is_empty());
+}
+
+#[test]
+fn bloom_capacity() {
+    let keys = SipKeys([(1, 2), (3, 4)]);
+    let mut bloom = Bloom::new_for_fp_rate(1000, 0.01).with_sip_keys(keys);
+    assert_eq!(bloom.capacity(), 1000);
+    assert!(bloom.target_fp_rate() <= 0.011);
+    assert_eq!(bloom.estimated_remaining(), 1000);
+    for i in 0..600u32 {
+        bloom.set(i);
+    }
+    assert!((350..=450).contains(&bloom.estimated_remaining()));
+    for i in 600..1200u32 {
+        bloom.set(i);
+    }
+    assert_eq!(bloom.estimated_remaining(), 0);
+
+    let bloom = Bloom::new_with_k(1024, 7);
+    assert_eq!(bloom.capacity(), 811);
+}
+
+#[test]
+fn bloom_params_plan() {
+    let params = BloomParams::plan(1000, 0.01);
+    assert_eq!(params.bitmap_size, Bloom::compute_bitmap_size(1000, 0.01));
+    assert_eq!(params.bitmap_bits, params.bitmap_size as u64 * 8);
+    assert!(params.fp_rate > 0.0 && params.fp_rate <= 0.011);
+
+    let bloom = Bloom::new_for_fp_rate(1000, 0.01);
+    assert_eq!(params.k_num, bloom.number_of_hash_functions());
+    assert_eq!(params.bitmap_bits, bloom.number_of_bits());
+
+    let small = BloomParams::plan_for_memory(1000, params.bitmap_size / 2);
+    assert!(small.fp_rate > params.fp_rate);
+}
@@ -0,0 +1,220 @@
+//! Union of filters, including of filters of different sizes.
+//!
+//! Bit offsets are hashes reduced modulo the size of the bitmap, so when the
+//! size of a filter is a multiple of the size of another one (typically when
+//! both are powers of two), the larger one can be folded down to the size of
+//! the smaller one by OR-ing bit i into bit i % target_bits. The folded filter
+//! behaves as if its items had been recorded into a filter of that size.
+//!
+//! Filters can also be combined with the `|`, `|=`, `&` and `&=` operators,
+//! which panic if the filters are not compatible.
+
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign};
+
+use super::{BitVecJournal, Bloom, BloomError};
+
+impl Bloom {
+    /// Fold the filter down to target_bits bits.
+    /// target_bits must divide the number of bits of the filter.
+    pub fn fold(&self, target_bits: u64) -> Result<Bloom, BloomError> {
+        if target_bits == 0 || !self.bitmap_bits.is_multiple_of(target_bits) {
+            return Err(BloomError::SizeMismatch);
+        }
+        let mut bitmap = BitVecJournal::new(target_bits as usize);
+        if target_bits.is_multiple_of(64) {
+            let target_words = bitmap.elems.len();
+            for (i, word) in self.bitmap.elems.iter().enumerate() {
+                bitmap.elems[i % target_words] |= word;
+            }
+        } else {
+            for bit_offset in (0..self.bitmap_bits as usize).filter(|&i| self.bitmap.get(i)) {
+                bitmap.set(bit_offset % target_bits as usize);
+            }
+            bitmap.journal.clear();
+        }
+        Ok(Bloom {
+            bitmap,
+            bitmap_bits: target_bits,
+            k_num: self.k_num,
+            hasher: self.hasher.clone(),
+            items: self.items,
+            capacity: ((self.capacity as u128 * target_bits as u128 / self.bitmap_bits as u128) as u64).max(1),
+        })
+    }
+
+    /// Record all the items of another filter into this one.
+    ///
+    /// The filters must use the same hash functions. If their sizes differ,
+    /// the size of one must be a multiple of the size of the other, and the
+    /// largest one is folded down to the size of the smallest one first. When
+    /// this filter is the one being folded, it is replaced with its folded
+    /// version, and its journal is reset.
+    pub fn union(&mut self, other: &Bloom) -> Result<(), BloomError> {
+        if self.k_num != other.k_num {
+            return Err(BloomError::SizeMismatch);
+        }
+        if !self.hasher.is_compatible(&other.hasher) {
+            return Err(BloomError::IncompatibleSeeds);
+        }
+        if self.bitmap_bits > other.bitmap_bits {
+            *self = self.fold(other.bitmap_bits)?;
+        }
+        let folded;
+        let other = if other.bitmap_bits > self.bitmap_bits {
+            folded = other.fold(self.bitmap_bits)?;
+            &folded
+        } else {
+            other
+        };
+        for (i, word) in other.bitmap.elems.iter().enumerate() {
+            let value = self.bitmap.elems[i] | word;
+            if value != self.bitmap.elems[i] {
+                self.bitmap.set_word(i, value);
+            }
+        }
+        self.items = self.estimate_items();
+        Ok(())
+    }
+
+    /// Only keep the bits that are also set in another filter.
+    ///
+    /// The filters must have the same size and use the same hash functions.
+    /// Items present in both filters are still found afterwards, but the
+    /// rate of false positives can be higher than the one of a filter where
+    /// only these items were recorded.
+    pub fn intersect(&mut self, other: &Bloom) -> Result<(), BloomError> {
+        if self.k_num != other.k_num || self.bitmap_bits != other.bitmap_bits {
+            return Err(BloomError::SizeMismatch);
+        }
+        if !self.hasher.is_compatible(&other.hasher) {
+            return Err(BloomError::IncompatibleSeeds);
+        }
+        for (i, word) in other.bitmap.elems.iter().enumerate() {
+            let value = self.bitmap.elems[i] & word;
+            if value != self.bitmap.elems[i] {
+                self.bitmap.set_word(i, value);
+            }
+        }
+        self.items = self.estimate_items();
+        Ok(())
+    }
+}
+
+impl BitOrAssign<&Bloom> for Bloom {
+    /// Record all the items of another filter, see `union`.
+    /// Panics if the filters are not compatible.
+    fn bitor_assign(&mut self, other: &Bloom) {
+        if let Err(e) = self.union(other) {
+            panic!("cannot compute the union of bloom filters: {}", e);
+        }
+    }
+}
+
+impl BitOr<&Bloom> for Bloom {
+    type Output = Bloom;
+
+    fn bitor(mut self, other: &Bloom) -> Bloom {
+        self |= other;
+        self
+    }
+}
+
+impl BitAndAssign<&Bloom> for Bloom {
+    /// Only keep the bits that are also set in another filter, see `intersect`.
+    /// Panics if the filters are not compatible.
+    fn bitand_assign(&mut self, other: &Bloom) {
+        if let Err(e) = self.intersect(other) {
+            panic!("cannot compute the intersection of bloom filters: {}", e);
+        }
+    }
+}
+
+impl BitAnd<&Bloom> for Bloom {
+    type Output = Bloom;
+
+    fn bitand(mut self, other: &Bloom) -> Bloom {
+        self &= other;
+        self
+    }
+}
+
+#[test]
+fn bloom_fold() {
+    let mut large = Bloom::from_parts(&[0u64; 64], 4);
+    let mut small = Bloom::from_parts(&[0u64; 16], 4);
+    for i in 0..100u32 {
+        large.set(i);
+        small.set(i);
+    }
+    let folded = large.fold(small.number_of_bits()).unwrap();
+    assert_eq!(folded.bitmap.elems, small.bitmap.elems);
+    assert_eq!(large.fold(600).err(), Some(BloomError::SizeMismatch));
+
+    let folded = large.fold(8).unwrap();
+    assert_eq!(folded.number_of_bits(), 8);
+    assert!((0..100u32).all(|i| folded.check(i)));
+    assert_eq!(Bloom::from_bytes(&folded.to_bytes()).unwrap().to_bytes(), folded.to_bytes());
+
+    let folded = Bloom::new(512, 100).fold(8).unwrap();
+    assert_eq!(folded.capacity(), 1);
+    assert_eq!(Bloom::from_bytes(&folded.to_bytes()).unwrap().capacity(), 1);
+}
+
+#[test]
+fn bloom_union_different_sizes() {
+    let mut large = Bloom::from_parts(&[0u64; 64], 4);
+    let mut small = Bloom::from_parts(&[0u64; 16], 4);
+    for i in 0..100u32 {
+        large.set(i);
+    }
+    for i in 100..200u32 {
+        small.set(i);
+    }
+    let mut union = Bloom::from_parts(&[0u64; 16], 4);
+    union.union(&large).unwrap();
+    union.union(&small).unwrap();
+    assert!((0..200u32).all(|i| union.check(i)));
+
+    large.union(&small).unwrap();
+    assert_eq!(large.number_of_bits(), small.number_of_bits());
+    assert_eq!(large.bitmap.elems, union.bitmap.elems);
+
+    let other_k = Bloom::from_parts(&[0u64; 16], 3);
+    assert_eq!(large.union(&other_k), Err(BloomError::SizeMismatch));
+}
+
+#[test]
+fn bloom_operators() {
+    let mut a = Bloom::new(128, 100);
+    let mut b = Bloom::new(128, 100);
+    for i in 0..100u32 {
+        a.set(i);
+    }
+    for i in 50..150u32 {
+        b.set(i);
+    }
+    let union = Bloom::new(128, 100) | &a | &b;
+    assert!((0..150u32).all(|i| union.check(i)));
+
+    let mut intersection = Bloom::new(128, 100);
+    intersection |= &a;
+    intersection &= &b;
+    assert!((50..100u32).all(|i| intersection.check(i)));
+    assert!(intersection.bitmap.elems.iter().zip(a.bitmap.elems.iter()).all(|(i, a)| i & !a == 0));
+
+    a |= &b;
+    assert_eq!(a.bitmap.elems, union.bitmap.elems);
+    let a = a & &intersection;
+    assert_eq!(a.bitmap.elems, intersection.bitmap.elems);
+
+    let mut other_size = Bloom::new(64, 100);
+    assert_eq!(other_size.intersect(&b), Err(BloomError::SizeMismatch));
+}
+
+#[test]
+#[should_panic]
+fn bloom_operators_incompatible() {
+    let a = Bloom::new(128, 100);
+    let b = Bloom::new(128, 10);
+    let _ = a | &b;
+}
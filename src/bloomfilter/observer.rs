@@ -0,0 +1,288 @@
+//! Instrumentation of filters, for metrics exporters.
+//!
+//! An `ObservedBloom` wraps a filter and reports every insertion and check to
+//! a `BloomObserver`. `BloomCounters` is an observer that simply counts
+//! events with atomics, and can be shared with an exporter through an `Arc`.
+//!
+//! With `ObservedBloom::with_fp_threshold`, observers are also told once the
+//! expected rate of false positives of the filter crosses a limit, for
+//! example to rotate it. `FpAlert` turns a closure into such an observer.
+
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::{Bloom, BloomParams, BloomStats};
+
+/// Receiver of the events of an `ObservedBloom`.
+/// All the methods do nothing by default.
+pub trait BloomObserver {
+    /// Called after an item was recorded. new is true if recording it
+    /// changed at least one bit, see `Bloom::insert`.
+    fn on_insert(&self, new: bool) {
+        let _ = new;
+    }
+
+    /// Called after an item was checked, with the result of the check.
+    fn on_check(&self, present: bool) {
+        let _ = present;
+    }
+
+    /// Called by `ObservedBloom::publish_stats`, with the current state of
+    /// the filter, including its estimated rate of false positives.
+    fn on_stats(&self, stats: &BloomStats) {
+        let _ = stats;
+    }
+
+    /// Called once, after an insertion made the expected rate of false
+    /// positives exceed the threshold set with `ObservedBloom::with_fp_threshold`.
+    fn on_fp_threshold(&self, fp_rate: f64) {
+        let _ = fp_rate;
+    }
+}
+
+impl<O: BloomObserver + ?Sized> BloomObserver for Arc<O> {
+    fn on_insert(&self, new: bool) {
+        (**self).on_insert(new)
+    }
+
+    fn on_check(&self, present: bool) {
+        (**self).on_check(present)
+    }
+
+    fn on_stats(&self, stats: &BloomStats) {
+        (**self).on_stats(stats)
+    }
+
+    fn on_fp_threshold(&self, fp_rate: f64) {
+        (**self).on_fp_threshold(fp_rate)
+    }
+}
+
+/// Observer calling a closure with the expected rate of false positives when
+/// it crosses the threshold of an `ObservedBloom`, and ignoring other events
+pub struct FpAlert<F>(pub F);
+
+impl<F: Fn(f64)> BloomObserver for FpAlert<F> {
+    fn on_fp_threshold(&self, fp_rate: f64) {
+        (self.0)(fp_rate)
+    }
+}
+
+/// Observer counting events
+#[derive(Debug, Default)]
+pub struct BloomCounters {
+    inserts: AtomicU64,
+    new_items: AtomicU64,
+    checks: AtomicU64,
+    positives: AtomicU64,
+    fp_rate: AtomicU64,
+}
+
+impl BloomCounters {
+    /// Return the number of items recorded
+    pub fn inserts(&self) -> u64 {
+        self.inserts.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of recorded items that changed at least one bit
+    pub fn new_items(&self) -> u64 {
+        self.new_items.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of items checked
+    pub fn checks(&self) -> u64 {
+        self.checks.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of checks reporting an item as present
+    pub fn positives(&self) -> u64 {
+        self.positives.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of checks reporting an item as absent
+    pub fn negatives(&self) -> u64 {
+        self.checks().saturating_sub(self.positives())
+    }
+
+    /// Return the rate of false positives estimated the last time stats
+    /// were published, or 0.0 if they never were
+    pub fn fp_rate(&self) -> f64 {
+        f64::from_bits(self.fp_rate.load(Ordering::Relaxed))
+    }
+}
+
+impl BloomObserver for BloomCounters {
+    fn on_insert(&self, new: bool) {
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+        if new {
+            self.new_items.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn on_check(&self, present: bool) {
+        if present {
+            self.positives.fetch_add(1, Ordering::Relaxed);
+        }
+        self.checks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_stats(&self, stats: &BloomStats) {
+        self.fp_rate.store(stats.fp_rate.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Bloom filter reporting its activity to an observer
+pub struct ObservedBloom<O> {
+    bloom: Bloom,
+    observer: O,
+    fp_threshold: Option<f64>,
+    fp_threshold_crossed: bool,
+}
+
+impl<O: BloomObserver> ObservedBloom<O> {
+    /// Report the activity of a filter to an observer.
+    pub fn new(bloom: Bloom, observer: O) -> ObservedBloom<O> {
+        ObservedBloom {
+            bloom,
+            observer,
+            fp_threshold: None,
+            fp_threshold_crossed: false,
+        }
+    }
+
+    /// Tell the observer once the expected rate of false positives, derived
+    /// from the number of recorded items, exceeds fp_threshold.
+    /// This is cheap enough to be checked after every insertion, unlike
+    /// the current rate, that requires scanning the bitmap.
+    pub fn with_fp_threshold(self, fp_threshold: f64) -> ObservedBloom<O> {
+        ObservedBloom {
+            fp_threshold: Some(fp_threshold),
+            ..self
+        }
+    }
+
+    /// Return true if the expected rate of false positives exceeded the
+    /// threshold, and the filter should be replaced.
+    pub fn fp_threshold_crossed(&self) -> bool {
+        self.fp_threshold_crossed
+    }
+
+    /// Replace the filter, for example with an empty one once the threshold
+    /// was crossed, and return the previous one.
+    pub fn replace(&mut self, bloom: Bloom) -> Bloom {
+        self.fp_threshold_crossed = false;
+        std::mem::replace(&mut self.bloom, bloom)
+    }
+
+    /// Record the presence of an item.
+    pub fn set<T>(&mut self, item: T)
+        where T: Hash
+    {
+        self.insert(item);
+    }
+
+    /// Record the presence of an item, and return true if it was definitely
+    /// new, see `Bloom::insert`.
+    pub fn insert<T>(&mut self, item: T) -> bool
+        where T: Hash
+    {
+        let new = self.bloom.insert(item);
+        self.observer.on_insert(new);
+        if new && !self.fp_threshold_crossed {
+            if let Some(fp_threshold) = self.fp_threshold {
+                let bloom = &self.bloom;
+                let fp_rate = BloomParams::expected_fp_rate(bloom.bitmap_bits, bloom.k_num, bloom.approximate_len());
+                if fp_rate > fp_threshold {
+                    self.fp_threshold_crossed = true;
+                    self.observer.on_fp_threshold(fp_rate);
+                }
+            }
+        }
+        new
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        let present = self.bloom.check(item);
+        self.observer.on_check(present);
+        present
+    }
+
+    /// Report the current state of the filter to the observer.
+    /// This scans the bitmap, so it is meant to be called periodically,
+    /// for example when metrics are scraped, rather than after every update.
+    pub fn publish_stats(&self) {
+        self.observer.on_stats(&self.bloom.stats());
+    }
+
+    /// Return the filter
+    pub fn bloom(&self) -> &Bloom {
+        &self.bloom
+    }
+
+    /// Return the observer
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// Return the filter, and forget the observer
+    pub fn into_bloom(self) -> Bloom {
+        self.bloom
+    }
+}
+
+#[test]
+fn observed_bloom_counters() {
+    let counters = Arc::new(BloomCounters::default());
+    let mut bloom = ObservedBloom::new(Bloom::new_for_fp_rate(100, 0.001), counters.clone());
+    for i in 0..10u32 {
+        bloom.set(i);
+    }
+    assert!(!bloom.insert(3u32));
+    for i in 0..20u32 {
+        bloom.check(i);
+    }
+    assert_eq!(counters.inserts(), 11);
+    assert_eq!(counters.new_items(), 10);
+    assert_eq!(counters.checks(), 20);
+    assert!((10..=11).contains(&counters.positives()));
+    assert_eq!(counters.negatives(), 20 - counters.positives());
+
+    assert_eq!(counters.fp_rate(), 0.0);
+    bloom.publish_stats();
+    assert_eq!(counters.fp_rate(), bloom.bloom().current_fp_rate());
+    assert!(counters.fp_rate() > 0.0);
+}
+
+#[test]
+fn observed_bloom_fp_threshold() {
+    use std::cell::Cell;
+
+    let alerts = Cell::new(0);
+    let alert = FpAlert(|fp_rate: f64| {
+        assert!(fp_rate > 0.01);
+        alerts.set(alerts.get() + 1);
+    });
+    let mut bloom = ObservedBloom::new(Bloom::new_for_fp_rate(100, 0.01), alert).with_fp_threshold(0.01);
+    for i in 0..90u32 {
+        bloom.set(i);
+    }
+    assert!(!bloom.fp_threshold_crossed());
+    for i in 90..300u32 {
+        bloom.set(i);
+    }
+    assert!(bloom.fp_threshold_crossed());
+    assert_eq!(alerts.get(), 1);
+
+    let full = bloom.replace(Bloom::new_for_fp_rate(100, 0.01));
+    assert!(full.check(299u32));
+    assert!(!bloom.fp_threshold_crossed());
+    for i in 0..300u32 {
+        bloom.set(i);
+    }
+    assert_eq!(alerts.get(), 2);
+}
@@ -0,0 +1,234 @@
+//! Page-oriented on-disk persistence, where only modified pages are rewritten.
+//!
+//! The file is a sequence of pages of page_size bytes, each followed by the
+//! CRC-32 of its content, little-endian:
+//!
+//! * page 0: magic "BLMP", page_size: u32, then the header of the format
+//!   described in the `serialize` module, padded with zeros
+//! * pages 1..: the bitmap, as little-endian 64-bit words; the last page is
+//!   padded with zeros
+//!
+//! The journal of the filter tracks the words that were modified, so
+//! `flush_dirty_pages` only writes the pages they belong to, and the header.
+
+use std::fs::{File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::crc32;
+use super::serialize::{self, Header};
+use super::{Bloom, BloomError};
+
+const MAGIC: &[u8; 4] = b"BLMP";
+
+/// Bloom filter persisted in a page-structured file
+pub struct PagedBloom {
+    bloom: Bloom,
+    file: File,
+    page_size: usize,
+}
+
+impl PagedBloom {
+    /// Smallest page size, large enough for the header page
+    pub const MIN_PAGE_SIZE: usize = 128;
+
+    /// Largest page size, bounding the buffer allocated before a page of a
+    /// file is verified
+    pub const MAX_PAGE_SIZE: usize = 1 << 24;
+
+    /// Create a file at path, or truncate it, and write the whole filter to it.
+    /// page_size must be a multiple of 8, between `MIN_PAGE_SIZE` and
+    /// `MAX_PAGE_SIZE`.
+    pub fn create<P>(path: P, mut bloom: Bloom, page_size: usize) -> io::Result<PagedBloom>
+        where P: AsRef<Path>
+    {
+        assert!((PagedBloom::MIN_PAGE_SIZE..=PagedBloom::MAX_PAGE_SIZE).contains(&page_size) && page_size.is_multiple_of(8));
+        bloom.bitmap.drain();
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        let mut paged = PagedBloom { bloom, file, page_size };
+        let pages = paged.number_of_pages();
+        paged.write_header()?;
+        for page in 0..pages {
+            paged.write_page(page)?;
+        }
+        paged.file.sync_data()?;
+        Ok(paged)
+    }
+
+    /// Open a file previously written by `create`, and verify all its pages.
+    /// The bitmap only grows as pages are actually read.
+    pub fn open<P>(path: P) -> io::Result<PagedBloom>
+        where P: AsRef<Path>
+    {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let (header, page_size) = read_header(&mut file)?;
+        let mut page = vec![0u8; page_size + 4];
+        let words = header.words();
+        let mut elems = Vec::with_capacity(words.min(page_size / 8));
+        while elems.len() < words {
+            file.read_exact(&mut page)?;
+            let n = (words - elems.len()).min(page_size / 8);
+            serialize::decode_words(&verify(&page)?[..n * 8], &mut elems);
+        }
+        Ok(PagedBloom {
+            bloom: header.into_bloom(elems),
+            file,
+            page_size,
+        })
+    }
+
+    /// Record the presence of an item.
+    /// The change is only persisted by the next call to `flush_dirty_pages`.
+    pub fn set<T>(&mut self, item: T)
+        where T: Hash
+    {
+        self.bloom.set(item);
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        self.bloom.check(item)
+    }
+
+    /// Return the filter
+    pub fn bloom(&self) -> &Bloom {
+        &self.bloom
+    }
+
+    /// Return the filter, for updates that are persisted by the next call to
+    /// `flush_dirty_pages`. Its journal must not be drained, and it must not
+    /// be replaced with another filter.
+    pub fn bloom_mut(&mut self) -> &mut Bloom {
+        &mut self.bloom
+    }
+
+    /// Return the filter, and close the file
+    pub fn into_bloom(self) -> Bloom {
+        self.bloom
+    }
+
+    /// Return the page size
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Return the number of pages of the bitmap
+    pub fn number_of_pages(&self) -> usize {
+        (self.bloom.bitmap.elems.len() * 8).div_ceil(self.page_size)
+    }
+
+    /// Write the header and the pages that were modified since the previous
+    /// flush, and wait for them to reach the disk.
+    /// Return the number of bitmap pages that were written.
+    pub fn flush_dirty_pages(&mut self) -> io::Result<usize> {
+        let words_per_page = self.page_size / 8;
+        let mut pages: Vec<usize> = self.bloom.bitmap.drain().into_iter()
+            .map(|(e_index, _)| e_index / words_per_page)
+            .collect();
+        pages.sort_unstable();
+        pages.dedup();
+        self.write_header()?;
+        for &page in pages.iter() {
+            self.write_page(page)?;
+        }
+        self.file.sync_data()?;
+        Ok(pages.len())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        write_header(&mut self.file, self.page_size, &Header::for_bloom(&self.bloom))
+    }
+
+    fn write_page(&mut self, page: usize) -> io::Result<()> {
+        let words_per_page = self.page_size / 8;
+        let elems = &self.bloom.bitmap.elems;
+        let words = &elems[page * words_per_page..elems.len().min((page + 1) * words_per_page)];
+        let mut data = Vec::with_capacity(self.page_size + 4);
+        serialize::encode_words(words, &mut data);
+        write_at(&mut self.file, self.page_size, page + 1, data)
+    }
+}
+
+/// Read and verify the header page, and return the header and the page size
+pub(crate) fn read_header(file: &mut File) -> io::Result<(Header, usize)> {
+    let mut prefix = [0u8; 8];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut prefix)?;
+    if &prefix[..4] != MAGIC {
+        return Err(BloomError::corrupt("not a paged bloom filter"));
+    }
+    let page_size = u32::from_le_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]) as usize;
+    if !(PagedBloom::MIN_PAGE_SIZE..=PagedBloom::MAX_PAGE_SIZE).contains(&page_size) || !page_size.is_multiple_of(8) {
+        return Err(BloomError::corrupt("invalid page size"));
+    }
+    let mut page = vec![0u8; page_size + 4];
+    page[..8].copy_from_slice(&prefix);
+    file.read_exact(&mut page[8..])?;
+    let header = Header::read(&mut &verify(&page)?[8..])?;
+    Ok((header, page_size))
+}
+
+/// Write the header page
+pub(crate) fn write_header(file: &mut File, page_size: usize, header: &Header) -> io::Result<()> {
+    let mut page = Vec::with_capacity(page_size + 4);
+    page.extend_from_slice(MAGIC);
+    page.extend_from_slice(&(page_size as u32).to_le_bytes());
+    page.extend_from_slice(&header.encode());
+    write_at(file, page_size, 0, page)
+}
+
+/// Pad a page, append its checksum, and write it at its position
+pub(crate) fn write_at(file: &mut File, page_size: usize, position: usize, mut page: Vec<u8>) -> io::Result<()> {
+    page.resize(page_size, 0);
+    let crc = crc32::checksum(&page);
+    page.extend_from_slice(&crc.to_le_bytes());
+    file.seek(SeekFrom::Start((position * (page_size + 4)) as u64))?;
+    file.write_all(&page)
+}
+
+/// Check the checksum of a page, and return its content
+pub(crate) fn verify(page: &[u8]) -> io::Result<&[u8]> {
+    let (data, crc) = page.split_at(page.len() - 4);
+    if crc32::checksum(data).to_le_bytes() != crc {
+        return Err(BloomError::corrupt("page checksum mismatch"));
+    }
+    Ok(data)
+}
+
+#[test]
+fn paged_bloom_flush_dirty_pages() {
+    let path = std::env::temp_dir().join(format!("bloomfilter-paged-{}", std::process::id()));
+    let mut bloom = Bloom::new(4096, 1000);
+    for i in 0..100u32 {
+        bloom.set(i);
+    }
+    let mut paged = PagedBloom::create(&path, bloom, 512).unwrap();
+    assert_eq!(paged.number_of_pages(), 8);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 9 * 516);
+    assert_eq!(paged.flush_dirty_pages().unwrap(), 0);
+    paged.set("key");
+    assert!(paged.flush_dirty_pages().unwrap() <= paged.bloom().number_of_hash_functions() as usize);
+    let expected = paged.bloom().to_bytes();
+    drop(paged);
+
+    let paged = PagedBloom::open(&path).unwrap();
+    assert_eq!(paged.page_size(), 512);
+    assert!(paged.check("key") && (0..100u32).all(|i| paged.check(i)));
+    assert_eq!(paged.into_bloom().to_bytes(), expected);
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[516 * 3 + 10] ^= 1;
+    std::fs::write(&path, &bytes).unwrap();
+    let err = PagedBloom::open(&path).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::CorruptData { reason: "page checksum mismatch" }));
+
+    bytes[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+    std::fs::write(&path, &bytes).unwrap();
+    let err = PagedBloom::open(&path).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::CorruptData { reason: "invalid page size" }));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,188 @@
+//! Bloom filter over key prefixes, for range scans.
+//!
+//! Keys are mapped to prefixes by a prefix extractor, and the prefixes are
+//! recorded instead of, or along with, the keys. A scan over the keys
+//! starting with a prefix can then be skipped when the filter says that no
+//! key with that prefix was recorded.
+//!
+//! Whether whole keys are recorded is chosen when the filter is built, and
+//! is part of its identity, like its extractor: a filter must always be used
+//! in the mode it was built with, including after being deserialized.
+
+use std::hash::Hash;
+
+use super::Bloom;
+
+/// Map keys to the prefixes that are recorded in a `PrefixBloom`
+pub trait PrefixExtractor {
+    /// Return the prefix of a key, or None if the key has no prefix.
+    /// Keys without a prefix are only found by whole key checks.
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+impl<F> PrefixExtractor for F
+    where F: Fn(&[u8]) -> Option<&[u8]>
+{
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        self(key)
+    }
+}
+
+/// Use the first n bytes of keys as prefixes. Shorter keys have no prefix.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedPrefix(pub usize);
+
+impl PrefixExtractor for FixedPrefix {
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.0)
+    }
+}
+
+/// Use at most the first n bytes of keys as prefixes.
+#[derive(Clone, Copy, Debug)]
+pub struct CappedPrefix(pub usize);
+
+impl PrefixExtractor for CappedPrefix {
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        Some(&key[..key.len().min(self.0)])
+    }
+}
+
+/// Bloom filter recording the prefixes of keys, and optionally the keys
+pub struct PrefixBloom<E> {
+    bloom: Bloom,
+    extractor: E,
+    whole_keys: bool,
+}
+
+impl<E> PrefixBloom<E>
+    where E: PrefixExtractor
+{
+    /// Create a new prefix bloom filter structure.
+    /// bitmap_size is the size in bytes (not bits) that will be allocated in memory
+    /// items_count is an estimation of the maximum number of prefixes and keys to store.
+    pub fn new(bitmap_size: usize, items_count: usize, extractor: E) -> PrefixBloom<E> {
+        PrefixBloom::from_bloom(Bloom::new(bitmap_size, items_count), extractor, false)
+    }
+
+    /// Create a new prefix bloom filter structure, that also records whole
+    /// keys, so that `check` can tell apart keys that share a prefix.
+    /// items_count is an estimation of the maximum number of prefixes and
+    /// keys to store: the filter needs room for both.
+    pub fn new_with_whole_keys(bitmap_size: usize, items_count: usize, extractor: E) -> PrefixBloom<E> {
+        PrefixBloom::from_bloom(Bloom::new(bitmap_size, items_count), extractor, true)
+    }
+
+    /// Create a new prefix bloom filter structure.
+    /// items_count is an estimation of the maximum number of prefixes and keys to store.
+    /// fp_p is the wanted rate of false positives, in ]0.0, 1.0[
+    pub fn new_for_fp_rate(items_count: usize, fp_p: f64, extractor: E) -> PrefixBloom<E> {
+        PrefixBloom::from_bloom(Bloom::new_for_fp_rate(items_count, fp_p), extractor, false)
+    }
+
+    /// Record prefixes, and whole keys if whole_keys is true, into an
+    /// existing filter, for example one that was deserialized. The extractor
+    /// and the mode must be the ones the filter was built with.
+    pub fn from_bloom(bloom: Bloom, extractor: E, whole_keys: bool) -> PrefixBloom<E> {
+        PrefixBloom {
+            bloom,
+            extractor,
+            whole_keys,
+        }
+    }
+
+    /// Return true if whole keys are recorded along with their prefixes
+    pub fn has_whole_keys(&self) -> bool {
+        self.whole_keys
+    }
+
+    /// Record the presence of a key.
+    pub fn set(&mut self, key: &[u8]) {
+        if let Some(prefix) = self.extractor.prefix(key) {
+            self.bloom.set(Entry::Prefix(prefix));
+        }
+        if self.whole_keys {
+            self.bloom.set(Entry::Key(key));
+        }
+    }
+
+    /// Check if a key may be present.
+    /// With whole keys, the key itself is checked. Otherwise, only its prefix
+    /// is, so keys sharing a prefix with a recorded key are false positives.
+    pub fn check(&self, key: &[u8]) -> bool {
+        if self.whole_keys {
+            self.bloom.check(Entry::Key(key))
+        } else {
+            self.check_prefix_of(key)
+        }
+    }
+
+    /// Check if keys with the same prefix as key may be present.
+    /// Keys without a prefix can't be ruled out, and true is returned.
+    pub fn check_prefix_of(&self, key: &[u8]) -> bool {
+        match self.extractor.prefix(key) {
+            Some(prefix) => self.bloom.check(Entry::Prefix(prefix)),
+            None => true,
+        }
+    }
+
+    /// Check if keys starting with prefix may be present.
+    /// prefix must be a prefix as returned by the extractor.
+    pub fn check_prefix(&self, prefix: &[u8]) -> bool {
+        self.bloom.check(Entry::Prefix(prefix))
+    }
+
+    /// Return the filter
+    pub fn bloom(&self) -> &Bloom {
+        &self.bloom
+    }
+
+    /// Return the filter, and forget the extractor
+    pub fn into_bloom(self) -> Bloom {
+        self.bloom
+    }
+}
+
+/// What is recorded into the filter, so that a prefix and a key with the
+/// same bytes are different items
+#[derive(Hash)]
+enum Entry<'a> {
+    Prefix(&'a [u8]),
+    Key(&'a [u8]),
+}
+
+#[test]
+fn prefix_bloom_check() {
+    let mut bloom = PrefixBloom::new_for_fp_rate(100, 0.001, FixedPrefix(4));
+    bloom.set(b"user:alice");
+    bloom.set(b"user:bob");
+    bloom.set(b"id");
+    assert!(bloom.check_prefix(b"user"));
+    assert!(bloom.check_prefix_of(b"user:carol"));
+    assert!(bloom.check(b"user:carol"));
+    assert!(!bloom.check_prefix(b"item"));
+    assert!(bloom.check(b"id"));
+    assert!(!bloom.bloom().check(Entry::Key(b"user:alice")));
+}
+
+#[test]
+fn prefix_bloom_whole_keys() {
+    fn until_colon(key: &[u8]) -> Option<&[u8]> {
+        key.iter().position(|&c| c == b':').map(|i| &key[..i])
+    }
+
+    let bloom = Bloom::new_for_fp_rate(100, 0.001);
+    let mut bloom = PrefixBloom::from_bloom(bloom, until_colon, true);
+    assert!(bloom.has_whole_keys());
+    bloom.set(b"user:alice");
+    bloom.set(b"session");
+    assert!(bloom.check(b"user:alice"));
+    assert!(!bloom.check(b"user:carol"));
+    assert!(bloom.check_prefix_of(b"user:carol"));
+    assert!(bloom.check(b"session"));
+    assert!(!bloom.check_prefix(b"session"));
+
+    let restored = PrefixBloom::from_bloom(Bloom::from_bytes(&bloom.bloom().to_bytes()).unwrap(), until_colon, true);
+    assert!(restored.check(b"user:alice") && !restored.check(b"user:carol"));
+    assert!(PrefixBloom::new_with_whole_keys(256, 20, FixedPrefix(4)).has_whole_keys());
+}
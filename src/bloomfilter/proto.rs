@@ -0,0 +1,104 @@
+//! Protocol buffers representation of filters, for exchanging them over gRPC.
+//!
+//! `BloomFilter` is the prost message described by `proto/bloomfilter.proto`.
+
+use std::convert::TryFrom;
+
+use super::serialize::{self, Header};
+use super::{Bloom, BloomError, HashAlgorithm, SipKeys};
+
+/// Message describing a filter: its parameters, keys and bitmap
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BloomFilter {
+    /// Size of the bitmap, in bits
+    #[prost(uint64, tag = "1")]
+    pub bitmap_bits: u64,
+    /// Number of hash functions
+    #[prost(uint32, tag = "2")]
+    pub k_num: u32,
+    /// SipHash keys: k0 and k1 of the first function, then of the second one
+    #[prost(fixed64, repeated, tag = "3")]
+    pub sip_keys: Vec<u64>,
+    /// Hash functions: 0 for SipHash-2-4, 1 for SipHash-1-3-128, 2 for XXH64,
+    /// 3 for XXH3-128, 4 for wyhash
+    #[prost(uint32, tag = "4")]
+    pub algorithm: u32,
+    /// Number of items recorded in the filter
+    #[prost(uint64, tag = "5")]
+    pub items: u64,
+    /// Number of items the filter was designed for. 0, the value of a
+    /// missing field, stands for the capacity implied by the parameters.
+    #[prost(uint64, tag = "6")]
+    pub capacity: u64,
+    /// Bitmap, as little-endian 64-bit words
+    #[prost(bytes = "vec", tag = "7")]
+    pub bitmap: Vec<u8>,
+}
+
+impl Bloom {
+    /// Convert the filter into a protocol buffers message.
+    pub fn to_proto(&self) -> BloomFilter {
+        let mut bitmap = Vec::with_capacity(self.bitmap.elems.len() * 8);
+        serialize::encode_words(&self.bitmap.elems, &mut bitmap);
+        let [(k0, k1), (k2, k3)] = self.sip_keys().0;
+        BloomFilter {
+            bitmap_bits: self.bitmap_bits,
+            k_num: self.k_num,
+            sip_keys: vec![k0, k1, k2, k3],
+            algorithm: self.hash_algorithm().id() as u32,
+            items: self.items,
+            capacity: self.capacity,
+            bitmap,
+        }
+    }
+
+    /// Build a filter from a protocol buffers message.
+    pub fn from_proto(message: &BloomFilter) -> Result<Bloom, BloomError> {
+        let sip_keys = match message.sip_keys[..] {
+            [k0, k1, k2, k3] => SipKeys([(k0, k1), (k2, k3)]),
+            [] => SipKeys::default(),
+            _ => return Err(BloomError::CorruptData { reason: "invalid number of SipHash keys" }),
+        };
+        let algorithm = u8::try_from(message.algorithm).ok()
+            .and_then(HashAlgorithm::from_id)
+            .ok_or(BloomError::CorruptData { reason: "unsupported hash algorithm" })?;
+        let header = Header {
+            bitmap_bits: message.bitmap_bits,
+            k_num: message.k_num,
+            sip_keys,
+            algorithm,
+            items: Some(message.items),
+            capacity: Some(message.capacity).filter(|&capacity| capacity > 0),
+        };
+        header.validate(Some(message.bitmap.len()))?;
+        let mut elems = Vec::with_capacity(message.bitmap.len() / 8);
+        serialize::decode_words(&message.bitmap, &mut elems);
+        Ok(header.into_bloom(elems))
+    }
+}
+
+#[test]
+fn bloom_proto_roundtrip() {
+    use prost::Message;
+
+    let mut bloom = Bloom::new(100, 50).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    for i in 0..50u32 {
+        bloom.set(i);
+    }
+    let encoded = bloom.to_proto().encode_to_vec();
+    let message = BloomFilter::decode(&encoded[..]).unwrap();
+    let restored = Bloom::from_proto(&message).unwrap();
+    assert_eq!(restored.to_bytes(), bloom.to_bytes());
+
+    let mut no_capacity = message.clone();
+    no_capacity.capacity = 0;
+    let restored = Bloom::from_proto(&no_capacity).unwrap();
+    assert_eq!(restored.capacity() as u64, super::implied_capacity(bloom.bitmap_bits, bloom.k_num));
+
+    let mut truncated = message.clone();
+    truncated.bitmap.pop();
+    assert!(matches!(Bloom::from_proto(&truncated), Err(BloomError::CorruptData { .. })));
+    let mut bad_keys = message;
+    bad_keys.sip_keys.pop();
+    assert!(Bloom::from_proto(&bad_keys).is_err());
+}
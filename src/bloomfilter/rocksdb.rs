@@ -0,0 +1,366 @@
+//! Full filter blocks in the formats used by RocksDB.
+//!
+//! A full filter block is the bitmap, split into 64-byte cache lines,
+//! followed by 5 bytes of metadata. Two implementations are supported:
+//!
+//! * the legacy Bloom filter (`format_version` < 5). Keys are hashed with the
+//!   32-bit hash of LevelDB, seeded with 0xbc9f1d34, and the probes of a key
+//!   all fall into the same cache line. The metadata is the number of probes
+//!   as a byte, and the number of cache lines as a little-endian u32.
+//! * the fast local Bloom filter (`format_version` = 5). Keys are hashed with
+//!   the 64-bit hash of RocksDB, whose lower half picks the cache line and
+//!   whose upper half the probes. The metadata is -1, 0 (the implementation),
+//!   the number of probes, and two reserved zero bytes.
+//!
+//! RocksDB computes 64-bit hashes with XXPH3, a frozen pre-release version of
+//! XXH3, which this crate doesn't implement: fast local filters are built
+//! and queried with hashes computed by the caller. Ribbon filters (-2) are
+//! not supported.
+
+use super::BloomError;
+
+/// Length of the metadata trailing the bitmap
+const METADATA_LEN: usize = 5;
+
+/// Size of a cache line, in bytes
+const CACHE_LINE_SIZE: usize = 64;
+
+/// Hash of a key for legacy filters, as computed by RocksDB's BloomHash
+pub fn legacy_hash(key: &[u8]) -> u32 {
+    const SEED: u32 = 0xbc9f1d34;
+    const M: u32 = 0xc6a4a793;
+    let mut h = SEED ^ (key.len() as u32).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(4);
+    for chunk in &mut chunks {
+        h = h.wrapping_add(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        h = h.wrapping_mul(M);
+        h ^= h >> 16;
+    }
+    // Tail bytes are sign-extended, as they are chars in the original code
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate().rev() {
+            h = h.wrapping_add((byte as i8 as u32) << (8 * i));
+        }
+        h = h.wrapping_mul(M);
+        h ^= h >> 24;
+    }
+    h
+}
+
+/// Builder of legacy full filter blocks
+pub struct LegacyBloomBuilder {
+    bits_per_key: u32,
+    hashes: Vec<u32>,
+}
+
+impl LegacyBloomBuilder {
+    /// Create a builder. bits_per_key is the `bits_per_key` setting of the
+    /// filter policy, that also sets the number of probes.
+    pub fn new(bits_per_key: u32) -> LegacyBloomBuilder {
+        assert!(bits_per_key > 0);
+        LegacyBloomBuilder {
+            bits_per_key,
+            hashes: Vec::new(),
+        }
+    }
+
+    /// Add a key
+    pub fn add_key(&mut self, key: &[u8]) {
+        self.add_hash(legacy_hash(key));
+    }
+
+    /// Add a key, given its hash computed with `legacy_hash`
+    pub fn add_hash(&mut self, hash: u32) {
+        // Like RocksDB, skip consecutive duplicates
+        if self.hashes.last() != Some(&hash) {
+            self.hashes.push(hash);
+        }
+    }
+
+    /// Return the number of probes per key
+    pub fn number_of_probes(&self) -> u32 {
+        (self.bits_per_key as f64 * 0.69).clamp(1.0, 30.0) as u32
+    }
+
+    /// Build the filter block
+    pub fn finish(&self) -> Vec<u8> {
+        let num_probes = self.number_of_probes();
+        let mut num_lines = 0;
+        if !self.hashes.is_empty() {
+            let total_bits = self.hashes.len() * self.bits_per_key as usize;
+            num_lines = total_bits.div_ceil(CACHE_LINE_SIZE * 8);
+            // An odd number of cache lines involves more bits in picking one
+            if num_lines.is_multiple_of(2) {
+                num_lines += 1;
+            }
+        }
+        let mut data = vec![0u8; num_lines * CACHE_LINE_SIZE + METADATA_LEN];
+        for &hash in self.hashes.iter() {
+            legacy_add_hash(&mut data, hash, num_lines as u32, num_probes, 6);
+        }
+        let len = data.len();
+        data[len - 5] = num_probes as u8;
+        data[len - 4..].copy_from_slice(&(num_lines as u32).to_le_bytes());
+        data
+    }
+}
+
+/// Builder of fast local full filter blocks
+pub struct FastLocalBloomBuilder {
+    millibits_per_key: u32,
+    hashes: Vec<u64>,
+}
+
+impl FastLocalBloomBuilder {
+    /// Create a builder. millibits_per_key is 1000 times the `bits_per_key`
+    /// setting of the filter policy, that also sets the number of probes.
+    pub fn new(millibits_per_key: u32) -> FastLocalBloomBuilder {
+        assert!(millibits_per_key > 0);
+        FastLocalBloomBuilder {
+            millibits_per_key,
+            hashes: Vec::new(),
+        }
+    }
+
+    /// Add a key, given its 64-bit RocksDB hash
+    pub fn add_hash(&mut self, hash: u64) {
+        if self.hashes.last() != Some(&hash) {
+            self.hashes.push(hash);
+        }
+    }
+
+    /// Return the number of probes per key, picked like RocksDB does
+    pub fn number_of_probes(&self) -> u32 {
+        match self.millibits_per_key {
+            0..=2080 => 1,
+            2081..=3580 => 2,
+            3581..=5100 => 3,
+            5101..=6640 => 4,
+            6641..=8300 => 5,
+            8301..=10070 => 6,
+            10071..=11720 => 7,
+            11721..=14001 => 8,
+            14002..=16050 => 9,
+            16051..=18300 => 10,
+            18301..=22001 => 11,
+            22002..=25501 => 12,
+            25502..=50000 => (self.millibits_per_key - 1) / 2000 - 1,
+            _ => 24,
+        }
+    }
+
+    /// Build the filter block
+    pub fn finish(&self) -> Vec<u8> {
+        let num_probes = self.number_of_probes();
+        let mut num_lines = 0;
+        if !self.hashes.is_empty() {
+            let total_millibits = self.hashes.len() as u64 * self.millibits_per_key as u64;
+            num_lines = total_millibits.div_ceil(CACHE_LINE_SIZE as u64 * 8 * 1000) as usize;
+        }
+        let len_bytes = num_lines * CACHE_LINE_SIZE;
+        let mut data = vec![0u8; len_bytes + METADATA_LEN];
+        for &hash in self.hashes.iter() {
+            let line = fast_local_line(hash as u32, len_bytes);
+            let cache_line = &mut data[line..line + CACHE_LINE_SIZE];
+            for bit_pos in fast_local_probes((hash >> 32) as u32, num_probes) {
+                cache_line[bit_pos >> 3] |= 1 << (bit_pos & 7);
+            }
+        }
+        data[len_bytes] = 0xff;
+        data[len_bytes + 1] = 0;
+        data[len_bytes + 2] = num_probes as u8;
+        data
+    }
+}
+
+/// Full filter block, as read from a table file
+pub enum FullFilter<'a> {
+    /// Filter of a table without keys, that never matches
+    Empty,
+    /// Legacy Bloom filter
+    Legacy(LegacyBloomReader<'a>),
+    /// Fast local Bloom filter
+    FastLocal(FastLocalBloomReader<'a>),
+}
+
+impl<'a> FullFilter<'a> {
+    /// Parse a filter block, without copying it.
+    pub fn parse(data: &'a [u8]) -> Result<FullFilter<'a>, BloomError> {
+        if data.len() <= METADATA_LEN {
+            return Ok(FullFilter::Empty);
+        }
+        let (bitmap, metadata) = data.split_at(data.len() - METADATA_LEN);
+        match metadata[0] as i8 {
+            -1 => {
+                if metadata[1] != 0 {
+                    return Err(BloomError::CorruptData { reason: "unsupported filter implementation" });
+                }
+                let num_probes = (metadata[2] & 0x1f) as u32;
+                if metadata[2] >> 5 != 0 || num_probes == 0 {
+                    return Err(BloomError::CorruptData { reason: "unsupported filter parameters" });
+                }
+                if !bitmap.len().is_multiple_of(CACHE_LINE_SIZE) {
+                    return Err(BloomError::CorruptData { reason: "invalid filter length" });
+                }
+                Ok(FullFilter::FastLocal(FastLocalBloomReader { bitmap, num_probes }))
+            }
+            num_probes if num_probes >= 1 => {
+                let num_lines = u32::from_le_bytes([metadata[1], metadata[2], metadata[3], metadata[4]]);
+                if num_lines == 0 || !bitmap.len().is_multiple_of(num_lines as usize) ||
+                   !(bitmap.len() / num_lines as usize).is_power_of_two() {
+                    return Err(BloomError::CorruptData { reason: "invalid filter length" });
+                }
+                let line_size = bitmap.len() / num_lines as usize;
+                Ok(FullFilter::Legacy(LegacyBloomReader {
+                    bitmap,
+                    num_lines,
+                    num_probes: num_probes as u32,
+                    log2_line_size: line_size.trailing_zeros(),
+                }))
+            }
+            _ => Err(BloomError::CorruptData { reason: "unsupported filter implementation" }),
+        }
+    }
+}
+
+/// Reader of legacy full filter blocks
+pub struct LegacyBloomReader<'a> {
+    bitmap: &'a [u8],
+    num_lines: u32,
+    num_probes: u32,
+    log2_line_size: u32,
+}
+
+impl LegacyBloomReader<'_> {
+    /// Check if a key may be present.
+    /// There can be false positives, but no false negatives.
+    pub fn may_match(&self, key: &[u8]) -> bool {
+        self.may_match_hash(legacy_hash(key))
+    }
+
+    /// Check if a key may be present, given its hash computed with `legacy_hash`
+    pub fn may_match_hash(&self, hash: u32) -> bool {
+        let line = (hash % self.num_lines) as usize;
+        let cache_line = &self.bitmap[line << self.log2_line_size..(line + 1) << self.log2_line_size];
+        legacy_probes(hash, self.num_probes, self.log2_line_size + 3)
+            .all(|bit_pos| cache_line[bit_pos / 8] & (1 << (bit_pos % 8)) != 0)
+    }
+
+    /// Return the number of probes per key
+    pub fn number_of_probes(&self) -> u32 {
+        self.num_probes
+    }
+}
+
+/// Reader of fast local full filter blocks
+pub struct FastLocalBloomReader<'a> {
+    bitmap: &'a [u8],
+    num_probes: u32,
+}
+
+impl FastLocalBloomReader<'_> {
+    /// Check if a key may be present, given its 64-bit RocksDB hash.
+    /// There can be false positives, but no false negatives.
+    pub fn may_match_hash(&self, hash: u64) -> bool {
+        let line = fast_local_line(hash as u32, self.bitmap.len());
+        let cache_line = &self.bitmap[line..line + CACHE_LINE_SIZE];
+        fast_local_probes((hash >> 32) as u32, self.num_probes)
+            .all(|bit_pos| cache_line[bit_pos >> 3] & (1 << (bit_pos & 7)) != 0)
+    }
+
+    /// Return the number of probes per key
+    pub fn number_of_probes(&self) -> u32 {
+        self.num_probes
+    }
+}
+
+fn legacy_add_hash(data: &mut [u8], hash: u32, num_lines: u32, num_probes: u32, log2_line_size: u32) {
+    let line = (hash % num_lines) as usize;
+    let cache_line = &mut data[line << log2_line_size..(line + 1) << log2_line_size];
+    for bit_pos in legacy_probes(hash, num_probes, log2_line_size + 3) {
+        cache_line[bit_pos / 8] |= 1 << (bit_pos % 8);
+    }
+}
+
+/// Bit positions of the probes of a legacy filter, within a cache line
+fn legacy_probes(hash: u32, num_probes: u32, log2_line_bits: u32) -> impl Iterator<Item = usize> {
+    let delta = hash.rotate_left(15);
+    (0..num_probes).scan(hash, move |h, _| {
+        let bit_pos = (*h & ((1 << log2_line_bits) - 1)) as usize;
+        *h = h.wrapping_add(delta);
+        Some(bit_pos)
+    })
+}
+
+/// Offset of the cache line of a fast local filter
+fn fast_local_line(h1: u32, len_bytes: usize) -> usize {
+    let num_lines = (len_bytes / CACHE_LINE_SIZE) as u64;
+    (((h1 as u64 * num_lines) >> 32) as usize) * CACHE_LINE_SIZE
+}
+
+/// Bit positions of the probes of a fast local filter, within a cache line
+fn fast_local_probes(h2: u32, num_probes: u32) -> impl Iterator<Item = usize> {
+    (0..num_probes).scan(h2, |h, _| {
+        let bit_pos = (*h >> (32 - 9)) as usize;
+        *h = h.wrapping_mul(0x9e3779b9);
+        Some(bit_pos)
+    })
+}
+
+#[test]
+fn rocksdb_legacy_hash() {
+    // Values from RocksDB's hash tests
+    assert_eq!(legacy_hash(b""), 3164544308);
+    assert_eq!(legacy_hash(b"\x08"), 422599524);
+    assert_eq!(legacy_hash(b"\x9a"), 3195034349);
+    assert_eq!(legacy_hash(b"\x4d\x76"), 2447836956);
+    assert_eq!(legacy_hash(b"\x52\xd5"), 3854228105);
+    assert_eq!(legacy_hash(b"\x91\xf7"), 31066776);
+}
+
+#[test]
+fn rocksdb_legacy_filter() {
+    let mut builder = LegacyBloomBuilder::new(10);
+    for i in 0..1000u32 {
+        builder.add_key(&i.to_be_bytes());
+    }
+    let data = builder.finish();
+    assert_eq!(data.len(), 21 * 64 + 5);
+    assert_eq!(&data[data.len() - 5..], &[6, 21, 0, 0, 0]);
+    let reader = match FullFilter::parse(&data).unwrap() {
+        FullFilter::Legacy(reader) => reader,
+        _ => panic!("legacy filter expected"),
+    };
+    assert_eq!(reader.number_of_probes(), 6);
+    assert!((0..1000u32).all(|i| reader.may_match(&i.to_be_bytes())));
+    let false_positives = (1000..11000u32).filter(|i| reader.may_match(&i.to_be_bytes())).count();
+    assert!(false_positives < 300);
+
+    assert!(matches!(FullFilter::parse(&LegacyBloomBuilder::new(10).finish()), Ok(FullFilter::Empty)));
+    assert!(FullFilter::parse(&data[1..]).is_err());
+}
+
+#[test]
+fn rocksdb_fast_local_filter() {
+    let hash = |i: u64| i.wrapping_mul(0x9e3779b97f4a7c15).rotate_left(29);
+    let mut builder = FastLocalBloomBuilder::new(10000);
+    for i in 0..1000u64 {
+        builder.add_hash(hash(i));
+    }
+    let data = builder.finish();
+    assert_eq!(data.len(), 20 * 64 + 5);
+    assert_eq!(&data[data.len() - 5..], &[0xff, 0, 6, 0, 0]);
+    let reader = match FullFilter::parse(&data).unwrap() {
+        FullFilter::FastLocal(reader) => reader,
+        _ => panic!("fast local filter expected"),
+    };
+    assert!((0..1000u64).all(|i| reader.may_match_hash(hash(i))));
+    let false_positives = (1000..11000u64).filter(|&i| reader.may_match_hash(hash(i))).count();
+    assert!(false_positives < 300);
+
+    let mut ribbon = data.clone();
+    let len = ribbon.len();
+    ribbon[len - 5] = 0xfe;
+    assert!(FullFilter::parse(&ribbon).is_err());
+}
@@ -0,0 +1,142 @@
+//! Logical filter split into shards held by different processes or hosts.
+//!
+//! Every item is routed to a single shard, derived from its hashes, so that
+//! each node only has to hold and update its own shard. All the shards have
+//! the same parameters and hash functions: the router computes the same
+//! routing everywhere, each shard can be serialized and shipped on its own,
+//! and their union gives a view of the whole set in a single filter.
+
+use std::hash::Hash;
+use std::io;
+
+use super::sharded::{check_compatible, route};
+use super::{Bloom, BloomError, BloomParams, SipKeys};
+
+/// Filter made of shards with identical parameters, items being routed to
+/// one of them
+pub struct ShardedRouter {
+    shards: Vec<Bloom>,
+}
+
+impl ShardedRouter {
+    /// Create a router over `shards` empty filters, each one with the planned
+    /// parameters.
+    /// The global view holds all the items in a filter of the size of a
+    /// shard, so params should be planned for the total number of items if
+    /// the global view has to meet the rate of false positives, or for the
+    /// number of items of a shard if only the shards are queried.
+    pub fn new(shards: usize, params: &BloomParams) -> ShardedRouter {
+        assert!(shards > 0);
+        ShardedRouter {
+            shards: (0..shards).map(|_| Bloom::from_params(params)).collect(),
+        }
+    }
+
+    /// Use different SipHash keys for all the shards, see `Bloom::with_sip_keys`.
+    /// Every node must use the same keys, or items won't be routed consistently.
+    pub fn with_sip_keys(self, sip_keys: SipKeys) -> ShardedRouter {
+        ShardedRouter {
+            shards: self.shards.into_iter().map(|shard| shard.with_sip_keys(sip_keys)).collect(),
+        }
+    }
+
+    /// Build a router out of existing shards, for example after deserializing
+    /// them. Shards must be passed in the order `into_shards` returned them,
+    /// have the same parameters, and use the same hash functions.
+    pub fn from_shards(shards: Vec<Bloom>) -> Result<ShardedRouter, BloomError> {
+        let first = shards.first().ok_or(BloomError::InvalidParams)?;
+        for shard in shards.iter() {
+            check_compatible(first, shard)?;
+        }
+        Ok(ShardedRouter { shards })
+    }
+
+    /// Record the presence of an item in the shard it is routed to.
+    pub fn set<T>(&mut self, item: T)
+        where T: Hash
+    {
+        let hashes = self.shards[0].hasher.hash_pair(&item);
+        let index = route(hashes, self.shards.len());
+        self.shards[index].check_and_set_hashes(hashes);
+    }
+
+    /// Check if an item is present in the shard it is routed to.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        let hashes = self.shards[0].hasher.hash_pair(&item);
+        self.shards[route(hashes, self.shards.len())].check_hashes(hashes)
+    }
+
+    /// Return the index of the shard an item is routed to
+    pub fn shard_for<T>(&self, item: T) -> usize
+        where T: Hash
+    {
+        route(self.shards[0].hasher.hash_pair(&item), self.shards.len())
+    }
+
+    /// Return the number of shards
+    pub fn number_of_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Return a shard, for example to serialize it with `Bloom::to_bytes`
+    pub fn shard(&self, index: usize) -> &Bloom {
+        &self.shards[index]
+    }
+
+    /// Replace a shard with one serialized by another node, after checking
+    /// that it has the parameters and hash functions of the other shards.
+    pub fn load_shard(&mut self, index: usize, bytes: &[u8]) -> io::Result<()> {
+        let shard = Bloom::from_bytes(bytes)?;
+        check_compatible(&self.shards[index], &shard)?;
+        self.shards[index] = shard;
+        Ok(())
+    }
+
+    /// Return the union of all the shards, where all the items recorded in
+    /// any shard can be checked directly.
+    pub fn global_view(&self) -> Bloom {
+        let mut view = self.shards[0].empty_like();
+        for shard in self.shards.iter() {
+            view.union(shard).expect("shards are compatible");
+        }
+        view
+    }
+
+    /// Return the shards, in routing order.
+    pub fn into_shards(self) -> Vec<Bloom> {
+        self.shards
+    }
+}
+
+#[test]
+fn sharded_router_global_view() {
+    let params = BloomParams::plan(1000, 0.01);
+    let mut router = ShardedRouter::new(4, &params).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    for i in 0..1000u32 {
+        router.set(i);
+    }
+    assert!((0..1000u32).all(|i| router.check(i)));
+    let index = router.shard_for(42u32);
+    assert!(router.shard(index).check(42u32));
+    assert!((0..4).filter(|&other| other != index).all(|other| router.shard(other).approximate_len() > 0));
+
+    let view = router.global_view();
+    assert!((0..1000u32).all(|i| view.check(i)));
+    assert!((1000..2000u32).filter(|&i| view.check(i)).count() < 30);
+
+    // Another node rebuilds the router from the serialized shards
+    let mut other = ShardedRouter::new(4, &params).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    for index in 0..4 {
+        other.load_shard(index, &router.shard(index).to_bytes()).unwrap();
+    }
+    assert_eq!(other.global_view().to_bytes(), view.to_bytes());
+    let err = other.load_shard(0, &Bloom::from_params(&params).to_bytes()).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::IncompatibleSeeds));
+
+    let shards = router.into_shards();
+    assert!(ShardedRouter::from_shards(shards).unwrap().check(42u32));
+    assert_eq!(ShardedRouter::from_shards(vec![Bloom::new(64, 10), Bloom::new(128, 10)]).err(), Some(BloomError::SizeMismatch));
+}
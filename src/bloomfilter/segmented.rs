@@ -0,0 +1,309 @@
+//! Bloom filter larger than memory, whose bitmap is loaded from disk on demand.
+//!
+//! The filter is stored in the page-structured format of the `paged` module,
+//! and each page is a segment of the bitmap. Only the segments that were
+//! recently accessed are kept in memory, in an LRU cache of a fixed number of
+//! segments; the other ones are read, and have their checksum verified, when
+//! an item maps to them. Modified segments are written back when they are
+//! evicted, or by `flush`. Dropping the filter flushes it, ignoring errors.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::paged::{self, PagedBloom};
+use super::serialize::{self, Header};
+use super::{BitOffsets, BloomHasher, BloomParams, HashAlgorithm, SipKeys};
+
+struct Segment {
+    words: Vec<u64>,
+    dirty: bool,
+    // Position in the LRU order
+    last_use: u64,
+}
+
+/// Bloom filter stored on disk, with a cache of segments in memory
+pub struct SegmentedBloom {
+    file: File,
+    header: Header,
+    // The number of items changed since the header was written
+    header_dirty: bool,
+    hasher: BloomHasher,
+    segment_size: usize,
+    cache_segments: usize,
+    segments: HashMap<usize, Segment>,
+    // Segments of the cache, least recently used first
+    lru: BTreeMap<u64, usize>,
+    uses: u64,
+}
+
+impl SegmentedBloom {
+    /// Create a file at path, or truncate it, for an empty filter with the
+    /// planned parameters, without ever holding its bitmap in memory.
+    /// segment_size must be a multiple of 8, between
+    /// `PagedBloom::MIN_PAGE_SIZE` and `PagedBloom::MAX_PAGE_SIZE`. At most
+    /// cache_segments segments are kept in memory. Parameters that don't
+    /// describe a usable filter are rejected with `BloomError::InvalidParams`,
+    /// before the file is touched.
+    pub fn create<P>(path: P, params: &BloomParams, sip_keys: SipKeys, segment_size: usize, cache_segments: usize) -> io::Result<SegmentedBloom>
+        where P: AsRef<Path>
+    {
+        assert!((PagedBloom::MIN_PAGE_SIZE..=PagedBloom::MAX_PAGE_SIZE).contains(&segment_size) && segment_size.is_multiple_of(8));
+        let header = Header {
+            bitmap_bits: params.bitmap_bits,
+            k_num: params.k_num,
+            sip_keys,
+            algorithm: HashAlgorithm::default(),
+            items: Some(0),
+            capacity: Some(params.items_count as u64),
+        };
+        header.validate(None)?;
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        paged::write_header(&mut file, segment_size, &header)?;
+        let segments = (header.words() * 8).div_ceil(segment_size);
+        for segment in 0..segments {
+            paged::write_at(&mut file, segment_size, segment + 1, Vec::new())?;
+        }
+        file.sync_data()?;
+        Ok(SegmentedBloom::from_parts(file, header, segment_size, cache_segments))
+    }
+
+    /// Open a file previously written by `create` or `PagedBloom::create`.
+    /// Only the header is read: segments are verified when they are loaded.
+    pub fn open<P>(path: P, cache_segments: usize) -> io::Result<SegmentedBloom>
+        where P: AsRef<Path>
+    {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let (header, segment_size) = paged::read_header(&mut file)?;
+        Ok(SegmentedBloom::from_parts(file, header, segment_size, cache_segments))
+    }
+
+    fn from_parts(file: File, header: Header, segment_size: usize, cache_segments: usize) -> SegmentedBloom {
+        assert!(cache_segments > 0);
+        SegmentedBloom {
+            file,
+            hasher: header.hasher(),
+            header,
+            header_dirty: false,
+            segment_size,
+            cache_segments,
+            segments: HashMap::new(),
+            lru: BTreeMap::new(),
+            uses: 0,
+        }
+    }
+
+    /// Record the presence of an item.
+    /// The change is only persisted once its segments are evicted from the
+    /// cache, or by the next call to `flush`.
+    pub fn set<T>(&mut self, item: T) -> io::Result<()>
+        where T: Hash
+    {
+        self.insert(item).map(|_| ())
+    }
+
+    /// Record the presence of an item, and return true if it was definitely
+    /// new, see `Bloom::insert`.
+    pub fn insert<T>(&mut self, item: T) -> io::Result<bool>
+        where T: Hash
+    {
+        let mut new = false;
+        for bit_offset in self.bit_offsets(&item) {
+            let (segment, index, mask) = self.locate(bit_offset);
+            let segment = self.segment(segment)?;
+            if segment.words[index] & mask == 0 {
+                segment.words[index] |= mask;
+                segment.dirty = true;
+                new = true;
+            }
+        }
+        if new {
+            self.header.items = Some(self.header.items.unwrap_or(0) + 1);
+            self.header_dirty = true;
+        }
+        Ok(new)
+    }
+
+    /// Check if an item is present in the set, loading the segments it maps
+    /// to if they are not cached.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&mut self, item: T) -> io::Result<bool>
+        where T: Hash
+    {
+        for bit_offset in self.bit_offsets(&item) {
+            let (segment, index, mask) = self.locate(bit_offset);
+            if self.segment(segment)?.words[index] & mask == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Write the header and the cached segments that were modified, and wait
+    /// for them to reach the disk. Dropping the filter also flushes it, but
+    /// errors are then ignored: call `flush` to handle them.
+    /// Return the number of segments that were written.
+    pub fn flush(&mut self) -> io::Result<usize> {
+        let mut dirty: Vec<usize> = self.segments.iter()
+            .filter(|(_, segment)| segment.dirty)
+            .map(|(&segment, _)| segment)
+            .collect();
+        dirty.sort_unstable();
+        for &segment in dirty.iter() {
+            self.write_segment(segment)?;
+        }
+        paged::write_header(&mut self.file, self.segment_size, &self.header)?;
+        self.file.sync_data()?;
+        self.header_dirty = false;
+        Ok(dirty.len())
+    }
+
+    /// Return the number of bits in the filter
+    pub fn number_of_bits(&self) -> u64 {
+        self.header.bitmap_bits
+    }
+
+    /// Return the number of hash functions used for `check` and `set`
+    pub fn number_of_hash_functions(&self) -> u32 {
+        self.header.k_num
+    }
+
+    /// Return the number of items recorded in the filter, see `Bloom::approximate_len`
+    pub fn approximate_len(&self) -> usize {
+        self.header.items.unwrap_or(0) as usize
+    }
+
+    /// Return the size of the segments, in bytes
+    pub fn segment_size(&self) -> usize {
+        self.segment_size
+    }
+
+    /// Return the number of segments of the bitmap
+    pub fn number_of_segments(&self) -> usize {
+        (self.header.words() * 8).div_ceil(self.segment_size)
+    }
+
+    /// Return the number of segments currently held in memory
+    pub fn cached_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    fn bit_offsets<T>(&self, item: &T) -> BitOffsets
+        where T: Hash
+    {
+        self.hasher.bit_offsets(item, self.header.k_num, self.header.bitmap_bits)
+    }
+
+    /// Return the segment, word index within the segment and mask of a bit
+    fn locate(&self, bit_offset: usize) -> (usize, usize, u64) {
+        let words_per_segment = self.segment_size / 8;
+        let e_index = bit_offset / 64;
+        (e_index / words_per_segment, e_index % words_per_segment, 1u64 << (bit_offset % 64))
+    }
+
+    /// Return a segment, loading it and evicting the least recently used
+    /// one if it is not cached, and mark it as the most recently used one
+    fn segment(&mut self, segment: usize) -> io::Result<&mut Segment> {
+        self.uses += 1;
+        let last_use = self.uses;
+        if let Some(cached) = self.segments.get_mut(&segment) {
+            self.lru.remove(&cached.last_use);
+            cached.last_use = last_use;
+        } else {
+            if self.segments.len() >= self.cache_segments {
+                self.evict()?;
+            }
+            let words = self.read_segment(segment)?;
+            self.segments.insert(segment, Segment { words, dirty: false, last_use });
+        }
+        self.lru.insert(last_use, segment);
+        Ok(self.segments.get_mut(&segment).expect("the segment was just cached"))
+    }
+
+    /// Evict the least recently used segment. It is only removed from the
+    /// cache once written, so that a failed write loses no update.
+    fn evict(&mut self) -> io::Result<()> {
+        let (_, &segment) = self.lru.first_key_value().expect("the cache is full");
+        if self.segments[&segment].dirty {
+            self.write_segment(segment)?;
+        }
+        self.lru.pop_first();
+        self.segments.remove(&segment);
+        Ok(())
+    }
+
+    fn read_segment(&mut self, segment: usize) -> io::Result<Vec<u64>> {
+        let words_per_segment = self.segment_size / 8;
+        let n = (self.header.words() - segment * words_per_segment).min(words_per_segment);
+        let mut page = vec![0u8; self.segment_size + 4];
+        self.file.seek(SeekFrom::Start(((segment + 1) * (self.segment_size + 4)) as u64))?;
+        self.file.read_exact(&mut page)?;
+        // Segments always have room for a whole page, so that the last one
+        // needs no special case
+        let mut words = Vec::with_capacity(words_per_segment);
+        serialize::decode_words(&paged::verify(&page)?[..n * 8], &mut words);
+        words.resize(words_per_segment, 0);
+        Ok(words)
+    }
+
+    fn write_segment(&mut self, segment: usize) -> io::Result<()> {
+        let cached = self.segments.get_mut(&segment).expect("only cached segments are written");
+        let mut data = Vec::with_capacity(self.segment_size + 4);
+        serialize::encode_words(&cached.words, &mut data);
+        paged::write_at(&mut self.file, self.segment_size, segment + 1, data)?;
+        cached.dirty = false;
+        Ok(())
+    }
+}
+
+impl Drop for SegmentedBloom {
+    fn drop(&mut self) {
+        if self.header_dirty || self.segments.values().any(|segment| segment.dirty) {
+            let _ = self.flush();
+        }
+    }
+}
+
+#[test]
+fn segmented_bloom_cache() {
+    let path = std::env::temp_dir().join(format!("bloomfilter-segmented-{}", std::process::id()));
+    let params = BloomParams::plan(1000, 0.01);
+    let mut segmented = SegmentedBloom::create(&path, &params, SipKeys::default(), 128, 2).unwrap();
+    assert_eq!(segmented.number_of_segments(), params.bitmap_size.div_ceil(128));
+    assert_eq!(segmented.cached_segments(), 0);
+    let invalid = BloomParams { k_num: 0, ..params };
+    let err = SegmentedBloom::create(&path, &invalid, SipKeys::default(), 128, 2).err().unwrap();
+    assert_eq!(super::BloomError::from_io_error(&err), Some(&super::BloomError::InvalidParams));
+    for i in 0..500u32 {
+        segmented.set(i).unwrap();
+    }
+    assert_eq!(segmented.cached_segments(), 2);
+    assert!((0..500u32).all(|i| segmented.check(i).unwrap()));
+    assert!((495..=500).contains(&segmented.approximate_len()));
+    segmented.flush().unwrap();
+    assert_eq!(segmented.flush().unwrap(), 0);
+    let segmented_len = segmented.approximate_len();
+    drop(segmented);
+
+    let paged = PagedBloom::open(&path).unwrap();
+    assert!((0..500u32).all(|i| paged.check(i)));
+    let mut bloom = super::Bloom::from_params(&params);
+    bloom.insert_all(0..500u32);
+    assert_eq!(paged.bloom().bitmap.elems, bloom.bitmap.elems);
+    drop(paged);
+
+    let mut segmented = SegmentedBloom::open(&path, 1).unwrap();
+    assert_eq!(segmented.segment_size(), 128);
+    assert!((0..500u32).all(|i| segmented.check(i).unwrap()));
+    assert!(segmented.insert("key").unwrap());
+    assert!(segmented.check("key").unwrap());
+    assert_eq!(segmented.cached_segments(), 1);
+    // Dropping the filter flushes it
+    drop(segmented);
+    let paged = PagedBloom::open(&path).unwrap();
+    assert!(paged.check("key"));
+    assert_eq!(paged.bloom().approximate_len(), segmented_len + 1);
+    std::fs::remove_file(&path).unwrap();
+}
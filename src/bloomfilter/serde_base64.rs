@@ -0,0 +1,123 @@
+//! Text representation of filters, for JSON and other serde formats.
+//!
+//! With the `serde` feature, `Bloom` implements `Serialize` and `Deserialize`
+//! as a structure whose fields are the parameters of the filter, and whose
+//! bitmap is a base64 string of its little-endian 64-bit words:
+//!
+//! ```json
+//! {"bitmap_bits": 1024, "k_num": 8, "sip_keys": [[0, 0], [0, 0]],
+//!  "algorithm": 0, "items": 10, "capacity": 100, "bitmap": "AAAA..."}
+//! ```
+//!
+//! The functions of this module can also be used with
+//! `#[serde(with = "bloomfilter::serde_base64")]` to store a filter as a
+//! single base64 string of the binary format produced by `Bloom::to_bytes`.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use super::serialize::{self, Header};
+use super::{Bloom, HashAlgorithm, SipKeys};
+
+#[derive(Serialize, Deserialize)]
+struct BloomRepr {
+    bitmap_bits: u64,
+    k_num: u32,
+    sip_keys: [(u64, u64); 2],
+    algorithm: u8,
+    items: u64,
+    capacity: u64,
+    bitmap: String,
+}
+
+impl Serialize for Bloom {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut bytes = Vec::with_capacity(self.bitmap.elems.len() * 8);
+        serialize::encode_words(&self.bitmap.elems, &mut bytes);
+        BloomRepr {
+            bitmap_bits: self.bitmap_bits,
+            k_num: self.k_num,
+            sip_keys: self.sip_keys().0,
+            algorithm: self.hash_algorithm().id(),
+            items: self.items,
+            capacity: self.capacity,
+            bitmap: STANDARD.encode(&bytes),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bloom {
+    fn deserialize<D>(deserializer: D) -> Result<Bloom, D::Error>
+        where D: Deserializer<'de>
+    {
+        let repr = BloomRepr::deserialize(deserializer)?;
+        let algorithm = HashAlgorithm::from_id(repr.algorithm)
+            .ok_or_else(|| de::Error::custom("unsupported hash algorithm"))?;
+        let bytes = STANDARD.decode(&repr.bitmap).map_err(de::Error::custom)?;
+        let header = Header {
+            bitmap_bits: repr.bitmap_bits,
+            k_num: repr.k_num,
+            sip_keys: SipKeys(repr.sip_keys),
+            algorithm,
+            items: Some(repr.items),
+            capacity: Some(repr.capacity),
+        };
+        header.validate(Some(bytes.len())).map_err(de::Error::custom)?;
+        let mut elems = Vec::with_capacity(bytes.len() / 8);
+        serialize::decode_words(&bytes, &mut elems);
+        Ok(header.into_bloom(elems))
+    }
+}
+
+/// Serialize a filter as a base64 string of its binary representation
+pub fn serialize<S>(bloom: &Bloom, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    serializer.serialize_str(&STANDARD.encode(bloom.to_bytes()))
+}
+
+/// Deserialize a filter from a base64 string of its binary representation
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Bloom, D::Error>
+    where D: Deserializer<'de>
+{
+    let text = String::deserialize(deserializer)?;
+    let bytes = STANDARD.decode(&text).map_err(de::Error::custom)?;
+    Bloom::from_bytes(&bytes).map_err(de::Error::custom)
+}
+
+#[test]
+fn bloom_serde_json() {
+    let mut bloom = Bloom::new(100, 50).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    for i in 0..50u32 {
+        bloom.set(i);
+    }
+    let json = serde_json::to_string(&bloom).unwrap();
+    assert!(json.starts_with(r#"{"bitmap_bits":800,"k_num":12,"sip_keys":[[1,2],[3,4]],"algorithm":0,"#));
+    let restored: Bloom = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.to_bytes(), bloom.to_bytes());
+
+    let truncated = json.replace(r#""bitmap_bits":800"#, r#""bitmap_bits":900"#);
+    assert!(serde_json::from_str::<Bloom>(&truncated).is_err());
+}
+
+#[test]
+fn bloom_serde_with_base64() {
+    #[derive(Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        #[serde(with = "crate::serde_base64")]
+        filter: Bloom,
+    }
+
+    let mut config = Config { name: "blocked".to_string(), filter: Bloom::new(16, 10) };
+    config.filter.set("key");
+    let json = serde_json::to_string(&config).unwrap();
+    let restored: Config = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.name, "blocked");
+    assert_eq!(restored.filter.to_bytes(), config.filter.to_bytes());
+}
@@ -0,0 +1,448 @@
+//! Serialization of bloom filters.
+//!
+//! A serialized filter is a small header followed by the bitmap, stored as
+//! little-endian 64-bit words:
+//!
+//! * version: u8 (currently 2)
+//! * k_num: u32
+//! * bitmap_bits: u64
+//! * SipHash keys: 4 u64 (k0 and k1 of both functions)
+//! * hash algorithm: u8
+//! * number of items: u64
+//! * capacity: u64
+//! * bitmap: ceil(bitmap_bits / 64) u64 words
+//!
+//! Bit i of the bitmap is bit i % 8 of byte i / 8 of the serialized bitmap.
+//! Items are hashed the same way on all platforms, regardless of their
+//! endianness and word size, see `StableHasher`, so a filter serialized on
+//! one platform can be checked on any other one. Items whose `Hash`
+//! implementation itself depends on the platform, for example because it
+//! hashes pointers or native-endian bytes, are the exception.
+//!
+//! Version 1 headers stop after bitmap_bits: those filters were all built
+//! with the default keys and algorithm, their number of items is estimated
+//! from the bitmap, and their capacity is derived from the parameters.
+//!
+//! Readers and writers process the bitmap in chunks, so that a filter can be
+//! streamed to and from files or sockets without another copy of it in memory.
+//! With the `tokio` feature, the same format can be written and read
+//! asynchronously. It can also be exchanged as a hex string.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{bitmap_fits, implied_capacity, BitVecJournal, Bloom, BloomError, BloomHasher, HashAlgorithm, SipKeys, MAX_K_NUM};
+
+const FORMAT_VERSION: u8 = 2;
+
+/// Number of bitmap words processed at once by readers and writers
+const CHUNK_WORDS: usize = 1024;
+
+pub(crate) struct Header {
+    pub bitmap_bits: u64,
+    pub k_num: u32,
+    pub sip_keys: SipKeys,
+    pub algorithm: HashAlgorithm,
+    /// Unknown for version 1
+    pub items: Option<u64>,
+    /// Unknown for version 1
+    pub capacity: Option<u64>,
+}
+
+impl Header {
+    /// Length of a current header, including the version
+    const MAX_LEN: usize = 1 + 4 + 8 + 32 + 1 + 8 + 8;
+
+    pub fn for_bloom(bloom: &Bloom) -> Header {
+        Header {
+            bitmap_bits: bloom.bitmap_bits,
+            k_num: bloom.k_num,
+            sip_keys: bloom.sip_keys(),
+            algorithm: bloom.hash_algorithm(),
+            items: Some(bloom.items),
+            capacity: Some(bloom.capacity),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Header::MAX_LEN);
+        buf.push(FORMAT_VERSION);
+        buf.extend_from_slice(&self.k_num.to_le_bytes());
+        buf.extend_from_slice(&self.bitmap_bits.to_le_bytes());
+        for (k0, k1) in self.sip_keys.0.iter() {
+            buf.extend_from_slice(&k0.to_le_bytes());
+            buf.extend_from_slice(&k1.to_le_bytes());
+        }
+        buf.push(self.algorithm.id());
+        buf.extend_from_slice(&self.items.unwrap_or(0).to_le_bytes());
+        buf.extend_from_slice(&self.capacity.unwrap_or(0).to_le_bytes());
+        buf
+    }
+
+    /// Return the length of a header of the given version, including the version
+    pub fn len(version: u8) -> io::Result<usize> {
+        match version {
+            1 => Ok(1 + 4 + 8),
+            2 => Ok(Header::MAX_LEN),
+            _ => Err(BloomError::UnsupportedVersion { version }.into()),
+        }
+    }
+
+    /// Read a header. When reading from a slice, the slice is advanced past it.
+    pub fn read<R: Read>(r: &mut R) -> io::Result<Header> {
+        let mut buf = [0u8; Header::MAX_LEN];
+        r.read_exact(&mut buf[..1])?;
+        let len = Header::len(buf[0])?;
+        r.read_exact(&mut buf[1..len])?;
+        Header::decode(&buf[..len])
+    }
+
+    /// Decode a header, whose length was given by `len`
+    pub fn decode(buf: &[u8]) -> io::Result<Header> {
+        let version = buf[0];
+        let mut buf = &buf[1..];
+        let k_num = u32::from_le_bytes(take(&mut buf));
+        let bitmap_bits = u64::from_le_bytes(take(&mut buf));
+        let mut sip_keys = SipKeys::default();
+        let mut algorithm = HashAlgorithm::default();
+        let (mut items, mut capacity) = (None, None);
+        if version >= 2 {
+            for (k0, k1) in sip_keys.0.iter_mut() {
+                *k0 = u64::from_le_bytes(take(&mut buf));
+                *k1 = u64::from_le_bytes(take(&mut buf));
+            }
+            let [id] = take(&mut buf);
+            algorithm = HashAlgorithm::from_id(id).ok_or_else(|| BloomError::corrupt("unsupported hash algorithm"))?;
+            items = Some(u64::from_le_bytes(take(&mut buf)));
+            capacity = Some(u64::from_le_bytes(take(&mut buf)));
+        }
+        let header = Header {
+            bitmap_bits,
+            k_num,
+            sip_keys,
+            algorithm,
+            items,
+            capacity,
+        };
+        header.validate(None)?;
+        Ok(header)
+    }
+
+    /// Check the parameters, before building a filter out of them, and that
+    /// a bitmap of bitmap_size bytes matches them. Binary headers describe
+    /// the bitmap that follows them, and are checked without a size.
+    pub fn validate(&self, bitmap_size: Option<usize>) -> Result<(), BloomError> {
+        if self.bitmap_bits == 0 || self.k_num == 0 || self.k_num > MAX_K_NUM || self.capacity == Some(0)
+            || usize::try_from(self.bitmap_bits).is_err() {
+            return Err(BloomError::InvalidParams);
+        }
+        if bitmap_size.is_some_and(|size| size as u64 != self.bitmap_bits.div_ceil(64) * 8) {
+            return Err(BloomError::CorruptData { reason: "bitmap doesn't match the number of bits" });
+        }
+        Ok(())
+    }
+
+    /// Number of 64-bit words in the bitmap
+    pub fn words(&self) -> usize {
+        self.bitmap_bits.div_ceil(64) as usize
+    }
+
+    pub fn into_bloom(self, elems: Vec<u64>) -> Bloom {
+        assert!(bitmap_fits(self.bitmap_bits, elems.len()), "bitmap too small for {} bits", self.bitmap_bits);
+        let mut bloom = Bloom {
+            bitmap: BitVecJournal::from_vec(elems),
+            bitmap_bits: self.bitmap_bits,
+            k_num: self.k_num,
+            hasher: self.hasher(),
+            items: self.items.unwrap_or(0),
+            capacity: self.capacity.unwrap_or_else(|| implied_capacity(self.bitmap_bits, self.k_num)),
+        };
+        if self.items.is_none() {
+            bloom.items = bloom.estimate_items();
+        }
+        bloom
+    }
+
+    pub fn hasher(&self) -> BloomHasher {
+        BloomHasher::with_algorithm(self.sip_keys, self.algorithm)
+    }
+}
+
+fn take<const N: usize>(buf: &mut &[u8]) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    bytes.copy_from_slice(&buf[..N]);
+    *buf = &buf[N..];
+    bytes
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn encode_words(words: &[u64], buf: &mut Vec<u8>) {
+    buf.clear();
+    for word in words {
+        buf.extend_from_slice(&word.to_le_bytes());
+    }
+}
+
+pub(crate) fn decode_words(bytes: &[u8], elems: &mut Vec<u64>) {
+    elems.extend(bytes.chunks(8).map(|bytes| {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(bytes);
+        u64::from_le_bytes(word)
+    }));
+}
+
+impl Bloom {
+    /// Serialize the filter into a writer.
+    /// The bitmap is written in chunks, and is never copied as a whole.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&Header::for_bloom(self).encode())?;
+        let mut buf = Vec::with_capacity(CHUNK_WORDS * 8);
+        for chunk in self.bitmap.elems.chunks(CHUNK_WORDS) {
+            encode_words(chunk, &mut buf);
+            w.write_all(&buf)?;
+        }
+        Ok(())
+    }
+
+    /// Deserialize a filter previously serialized with `write_to`.
+    /// The bitmap is read in chunks, and only grows as data is actually read.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Bloom> {
+        let header = Header::read(r)?;
+        let words = header.words();
+        let mut elems = Vec::with_capacity(words.min(CHUNK_WORDS));
+        let mut buf = vec![0u8; CHUNK_WORDS * 8];
+        while elems.len() < words {
+            let n = (words - elems.len()).min(CHUNK_WORDS);
+            let chunk = &mut buf[..n * 8];
+            r.read_exact(chunk)?;
+            decode_words(chunk, &mut elems);
+        }
+        Ok(header.into_bloom(elems))
+    }
+
+    /// Asynchronously serialize the filter into a writer, using the same
+    /// format as `write_to`. The writer is flushed once the bitmap is written.
+    #[cfg(feature = "tokio")]
+    pub async fn write_to_async<W>(&self, w: &mut W) -> io::Result<()>
+        where W: AsyncWrite + Unpin
+    {
+        w.write_all(&Header::for_bloom(self).encode()).await?;
+        let mut buf = Vec::with_capacity(CHUNK_WORDS * 8);
+        for chunk in self.bitmap.elems.chunks(CHUNK_WORDS) {
+            encode_words(chunk, &mut buf);
+            w.write_all(&buf).await?;
+        }
+        w.flush().await
+    }
+
+    /// Asynchronously deserialize a filter previously serialized with
+    /// `write_to` or `write_to_async`.
+    #[cfg(feature = "tokio")]
+    pub async fn read_from_async<R>(r: &mut R) -> io::Result<Bloom>
+        where R: AsyncRead + Unpin
+    {
+        let mut header = [0u8; Header::MAX_LEN];
+        r.read_exact(&mut header[..1]).await?;
+        let len = Header::len(header[0])?;
+        r.read_exact(&mut header[1..len]).await?;
+        let header = Header::decode(&header[..len])?;
+        let words = header.words();
+        let mut elems = Vec::with_capacity(words.min(CHUNK_WORDS));
+        let mut buf = vec![0u8; CHUNK_WORDS * 8];
+        while elems.len() < words {
+            let n = (words - elems.len()).min(CHUNK_WORDS);
+            let chunk = &mut buf[..n * 8];
+            r.read_exact(chunk).await?;
+            decode_words(chunk, &mut elems);
+        }
+        Ok(header.into_bloom(elems))
+    }
+
+    /// Serialize the filter into a vector of bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Header::MAX_LEN + self.bitmap.elems.len() * 8);
+        self.write_to(&mut bytes).expect("writing to a vector cannot fail");
+        bytes
+    }
+
+    /// Deserialize a filter previously serialized with `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Bloom> {
+        Bloom::read_from(&mut &bytes[..])
+    }
+
+    /// Serialize the filter as a lowercase hex string of the output of
+    /// `to_bytes`, header included, with a 0x prefix.
+    pub fn to_hex(&self) -> String {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let bytes = self.to_bytes();
+        let mut hex = String::with_capacity(2 + bytes.len() * 2);
+        hex.push_str("0x");
+        for byte in bytes {
+            hex.push(DIGITS[(byte >> 4) as usize] as char);
+            hex.push(DIGITS[(byte & 0xf) as usize] as char);
+        }
+        hex
+    }
+
+    /// Deserialize a filter previously serialized with `to_hex`.
+    /// The 0x prefix is optional, and digits can be uppercase.
+    pub fn from_hex(hex: &str) -> io::Result<Bloom> {
+        let digits = hex.strip_prefix("0x").or_else(|| hex.strip_prefix("0X")).unwrap_or(hex).as_bytes();
+        if !digits.len().is_multiple_of(2) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "odd number of hex digits"));
+        }
+        let mut bytes = Vec::with_capacity(digits.len() / 2);
+        for (i, pair) in digits.chunks(2).enumerate() {
+            let digit = |c: u8| (c as char).to_digit(16).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData,
+                               format!("invalid hex digit {:?} in byte {}", c as char, i))
+            });
+            bytes.push((digit(pair[0])? << 4 | digit(pair[1])?) as u8);
+        }
+        Bloom::from_bytes(&bytes)
+    }
+}
+
+#[test]
+fn bloom_serialize_roundtrip() {
+    let mut bloom = Bloom::new(3 * 8 * CHUNK_WORDS + 5, 1000);
+    for i in 0..1000u32 {
+        bloom.set(i);
+    }
+    let bytes = bloom.to_bytes();
+    assert_eq!(bytes.len(), Header::MAX_LEN + bloom.bitmap.elems.len() * 8);
+
+    let restored = Bloom::from_bytes(&bytes).unwrap();
+    assert_eq!(restored.number_of_bits(), bloom.number_of_bits());
+    assert_eq!(restored.number_of_hash_functions(), bloom.number_of_hash_functions());
+    assert_eq!(restored.approximate_len(), bloom.approximate_len());
+    assert!((0..1000u32).all(|i| restored.check(i)));
+    assert_eq!(restored.to_bytes(), bytes);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn bloom_serialize_async_roundtrip() {
+    let mut bloom = Bloom::new(2 * 8 * CHUNK_WORDS + 3, 100);
+    for i in 0..100u32 {
+        bloom.set(i);
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let bytes = runtime.block_on(async {
+        let mut bytes = Vec::new();
+        bloom.write_to_async(&mut bytes).await.unwrap();
+        bytes
+    });
+    assert_eq!(bytes, bloom.to_bytes());
+
+    let restored = runtime.block_on(Bloom::read_from_async(&mut &bytes[..])).unwrap();
+    assert!((0..100u32).all(|i| restored.check(i)));
+    assert_eq!(restored.to_bytes(), bytes);
+}
+
+#[test]
+fn bloom_serialize_keys() {
+    let keys = SipKeys([(1, 2), (3, 4)]);
+    let mut bloom = Bloom::new(64, 10).with_sip_keys(keys);
+    bloom.set("key");
+    let restored = Bloom::from_bytes(&bloom.to_bytes()).unwrap();
+    assert_eq!(restored.sip_keys(), keys);
+    assert!(restored.check("key"));
+}
+
+#[test]
+fn bloom_serialize_hash_algorithm() {
+    let mut bloom = Bloom::new(64, 10).with_hash_algorithm(HashAlgorithm::SipHash13x128);
+    bloom.set("key");
+    let restored = Bloom::from_bytes(&bloom.to_bytes()).unwrap();
+    assert_eq!(restored.hash_algorithm(), HashAlgorithm::SipHash13x128);
+    assert!(restored.check("key"));
+}
+
+#[test]
+fn bloom_serialize_version_1() {
+    let mut bloom = Bloom::new(64, 10);
+    bloom.set("key");
+    let bytes = bloom.to_bytes();
+    let mut v1 = vec![1u8];
+    v1.extend_from_slice(&bytes[1..13]);
+    v1.extend_from_slice(&bytes[Header::MAX_LEN..]);
+    assert_eq!(Header::len(1).unwrap() + bloom.bitmap.elems.len() * 8, v1.len());
+    let restored = Bloom::from_bytes(&v1).unwrap();
+    assert_eq!(restored.sip_keys(), SipKeys::default());
+    assert_eq!(restored.to_bytes(), bytes);
+}
+
+#[test]
+fn bloom_serialize_hex() {
+    let mut bloom = Bloom::new(16, 10);
+    bloom.set("key");
+    let hex = bloom.to_hex();
+    assert!(hex.starts_with("0x02"));
+    assert_eq!(hex.len(), 2 + bloom.to_bytes().len() * 2);
+    assert_eq!(Bloom::from_hex(&hex).unwrap().to_bytes(), bloom.to_bytes());
+    assert_eq!(Bloom::from_hex(&hex[2..].to_uppercase()).unwrap().to_bytes(), bloom.to_bytes());
+
+    let err = Bloom::from_hex(&hex[..hex.len() - 1]).err().unwrap();
+    assert_eq!(err.to_string(), "odd number of hex digits");
+    let err = Bloom::from_hex(&hex.replacen("02", "0g", 1)).err().unwrap();
+    assert_eq!(err.to_string(), "invalid hex digit 'g' in byte 0");
+    assert!(Bloom::from_hex(&hex[..hex.len() - 2]).is_err());
+}
+
+#[test]
+fn bloom_serialize_invalid() {
+    let bloom = Bloom::new(32, 10);
+    let bytes = bloom.to_bytes();
+    assert!(Bloom::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+    let mut bad_version = bytes.clone();
+    bad_version[0] = 0xff;
+    let err = Bloom::from_bytes(&bad_version).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::UnsupportedVersion { version: 0xff }));
+
+    let mut no_bits = bytes.clone();
+    no_bits[5..13].copy_from_slice(&0u64.to_le_bytes());
+    let err = Bloom::from_bytes(&no_bits).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::InvalidParams));
+
+    let mut many_hashes = bytes.clone();
+    many_hashes[1..5].copy_from_slice(&(MAX_K_NUM + 1).to_le_bytes());
+    let err = Bloom::from_bytes(&many_hashes).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::InvalidParams));
+
+    let err = Bloom::from_bytes(&bytes[..10]).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(BloomError::from_io_error(&err), None);
+
+    let header = Header::for_bloom(&bloom);
+    assert_eq!(header.validate(Some(32)), Ok(()));
+    assert_eq!(header.validate(Some(24)), Err(BloomError::CorruptData { reason: "bitmap doesn't match the number of bits" }));
+}
+
+#[test]
+fn bloom_serialize_layout() {
+    let mut bloom = Bloom::new(16, 4);
+    bloom.set(1u32);
+    bloom.set("key");
+    bloom.set(vec![1u8, 2]);
+    // Filters written by any platform must be byte for byte identical:
+    // this pins the header, the bit offsets of the items and the bitmap layout.
+    let header = "0x02170000008000000000000000".to_string() + &"0".repeat(64) + "00" + "0300000000000000" + "0400000000000000";
+    assert_eq!(bloom.to_hex(), header + "294325a93105a7c59911132343c14919");
+
+    let bytes = bloom.to_bytes();
+    let bitmap = &bytes[Header::MAX_LEN..];
+    for bit in 0..128 {
+        assert_eq!(bitmap[bit / 8] & (1 << (bit % 8)) != 0, bloom.bitmap.get(bit));
+    }
+}
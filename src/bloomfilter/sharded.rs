@@ -0,0 +1,148 @@
+//! Bloom filter split into independently locked shards.
+
+use std::hash::Hash;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use super::{Bloom, BloomError, BloomHasher};
+
+/// Concurrent bloom filter made of independent sub-filters, each behind its
+/// own lock. Every item is routed to a single shard, so concurrent writers
+/// only contend when they hit the same shard, and each shard can be
+/// serialized or replaced on its own.
+pub struct ShardedBloom {
+    shards: Vec<RwLock<Bloom>>,
+    hasher: BloomHasher,
+}
+
+impl ShardedBloom {
+    /// Create a new sharded bloom filter.
+    /// bitmap_size is the total size in bytes of the shards' bitmaps,
+    /// items_count is an estimation of the maximum total number of items to store.
+    pub fn new(shards: usize, bitmap_size: usize, items_count: usize) -> ShardedBloom {
+        assert!(shards > 0);
+        let shard_bitmap_size = bitmap_size.div_ceil(shards);
+        let shard_items_count = items_count.div_ceil(shards);
+        let shards = (0..shards).map(|_| Bloom::new(shard_bitmap_size, shard_items_count)).collect();
+        ShardedBloom::from_shards(shards).expect("shards are built alike")
+    }
+
+    /// Create a new sharded bloom filter.
+    /// items_count is an estimation of the maximum total number of items to store.
+    /// fp_p is the wanted rate of false positives, in ]0.0, 1.0[
+    pub fn new_for_fp_rate(shards: usize, items_count: usize, fp_p: f64) -> ShardedBloom {
+        let bitmap_size = Bloom::compute_bitmap_size(items_count, fp_p);
+        ShardedBloom::new(shards, bitmap_size, items_count)
+    }
+
+    /// Build a sharded filter out of existing shards, for example after
+    /// deserializing them. Items are routed to shards by position, so shards
+    /// must be passed in the order `into_shards` returned them, have the same
+    /// parameters, and use the same hash functions.
+    pub fn from_shards(shards: Vec<Bloom>) -> Result<ShardedBloom, BloomError> {
+        let first = shards.first().ok_or(BloomError::InvalidParams)?;
+        for shard in shards.iter() {
+            check_compatible(first, shard)?;
+        }
+        let hasher = first.hasher.clone();
+        Ok(ShardedBloom {
+            shards: shards.into_iter().map(RwLock::new).collect(),
+            hasher,
+        })
+    }
+
+    /// Record the presence of an item.
+    pub fn set<T>(&self, item: T)
+        where T: Hash
+    {
+        let hashes = self.hasher.hash_pair(&item);
+        self.write_shard(self.shard_index(hashes)).check_and_set_hashes(hashes);
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        let hashes = self.hasher.hash_pair(&item);
+        self.read_shard(self.shard_index(hashes)).check_hashes(hashes)
+    }
+
+    /// Return the number of shards
+    pub fn number_of_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Return the index of the shard an item is stored in
+    pub fn shard_for<T>(&self, item: T) -> usize
+        where T: Hash
+    {
+        self.shard_index(self.hasher.hash_pair(&item))
+    }
+
+    /// Lock a shard for reading, for example to serialize it.
+    pub fn read_shard(&self, index: usize) -> RwLockReadGuard<'_, Bloom> {
+        self.shards[index].read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Lock a shard for writing, for example to merge another filter into it.
+    pub fn write_shard(&self, index: usize) -> RwLockWriteGuard<'_, Bloom> {
+        self.shards[index].write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Return the shards, in routing order.
+    pub fn into_shards(self) -> Vec<Bloom> {
+        self.shards.into_iter().map(|shard| shard.into_inner().unwrap_or_else(|e| e.into_inner())).collect()
+    }
+
+    fn shard_index(&self, hashes: [u64; 2]) -> usize {
+        route(hashes, self.shards.len())
+    }
+}
+
+/// Return the index of the shard, out of shards, items with these hashes
+/// are routed to.
+/// Uses the upper bits of the second hash, which are the least involved
+/// in the bit offsets within a shard.
+pub(crate) fn route(hashes: [u64; 2], shards: usize) -> usize {
+    (((hashes[1] >> 32) * shards as u64) >> 32) as usize
+}
+
+/// Check that two shards have the same parameters and hash functions
+pub(crate) fn check_compatible(shard: &Bloom, other: &Bloom) -> Result<(), BloomError> {
+    if shard.bitmap_bits != other.bitmap_bits || shard.k_num != other.k_num {
+        return Err(BloomError::SizeMismatch);
+    }
+    if !shard.hasher.is_compatible(&other.hasher) {
+        return Err(BloomError::IncompatibleSeeds);
+    }
+    Ok(())
+}
+
+#[test]
+fn sharded_bloom_concurrent_set() {
+    let bloom = std::sync::Arc::new(ShardedBloom::new_for_fp_rate(8, 4000, 0.01));
+    let writers: Vec<_> = (0..4u32).map(|t| {
+        let bloom = bloom.clone();
+        std::thread::spawn(move || {
+            for i in 0..1000u32 {
+                bloom.set(t * 1000 + i);
+            }
+        })
+    }).collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert!((0..4000u32).all(|i| bloom.check(i)));
+
+    let bloom = std::sync::Arc::try_unwrap(bloom).ok().unwrap();
+    let index = bloom.shard_for(42u32);
+    let bytes = bloom.read_shard(index).to_bytes();
+    let mut shards = bloom.into_shards();
+    assert_eq!(shards.len(), 8);
+    assert!(shards.iter().filter(|shard| shard.how_full() > 0.0).count() > 1);
+    shards[index] = Bloom::from_bytes(&bytes).unwrap();
+    let bloom = ShardedBloom::from_shards(shards).unwrap();
+    assert!(bloom.check(42u32));
+    let mixed = vec![Bloom::new(64, 10), Bloom::new(128, 10)];
+    assert_eq!(ShardedBloom::from_shards(mixed).err(), Some(BloomError::SizeMismatch));
+}
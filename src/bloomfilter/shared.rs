@@ -0,0 +1,83 @@
+//! Immutable bloom filter that can be shared between threads.
+
+use std::hash::Hash;
+use std::sync::Arc;
+
+use super::{BitOffsets, Bloom, BloomHasher};
+
+/// Read-only bloom filter whose bitmap is reference counted.
+/// Cloning a `SharedBloom` is O(1) and never copies the bitmap, so a single
+/// large filter can be handed to many worker threads.
+#[derive(Clone)]
+pub struct SharedBloom {
+    bitmap: Arc<[u64]>,
+    bitmap_bits: u64,
+    k_num: u32,
+    hasher: BloomHasher,
+    items: u64,
+}
+
+impl SharedBloom {
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        self.bit_offsets(&item).all(|bit_offset| self.bitmap[bit_offset / 64] & (1u64 << (bit_offset % 64)) != 0)
+    }
+
+    /// Return the number of bits in the filter
+    pub fn number_of_bits(&self) -> u64 {
+        self.bitmap_bits
+    }
+
+    /// Return the number of hash functions used for `check`
+    pub fn number_of_hash_functions(&self) -> u32 {
+        self.k_num
+    }
+
+    /// Return the number of items recorded in the filter, see `Bloom::approximate_len`
+    pub fn approximate_len(&self) -> usize {
+        self.items as usize
+    }
+
+    fn bit_offsets<T>(&self, item: &T) -> BitOffsets
+        where T: Hash
+    {
+        self.hasher.bit_offsets(item, self.k_num, self.bitmap_bits)
+    }
+}
+
+impl From<Bloom> for SharedBloom {
+    fn from(bloom: Bloom) -> SharedBloom {
+        SharedBloom {
+            bitmap: bloom.bitmap.elems.into(),
+            bitmap_bits: bloom.bitmap_bits,
+            k_num: bloom.k_num,
+            hasher: bloom.hasher,
+            items: bloom.items,
+        }
+    }
+}
+
+#[test]
+fn shared_bloom_check() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let mut bloom = Bloom::new(100, 50);
+    for i in 0..50u32 {
+        bloom.set(i);
+    }
+    let expected: Vec<bool> = (0..200u32).map(|i| bloom.check(i)).collect();
+    let shared = SharedBloom::from(bloom);
+    assert_send_sync(&shared);
+
+    let workers: Vec<_> = (0..4).map(|_| {
+        let shared = shared.clone();
+        std::thread::spawn(move || (0..200u32).map(|i| shared.check(i)).collect::<Vec<bool>>())
+    }).collect();
+    for worker in workers {
+        assert_eq!(worker.join().unwrap(), expected);
+    }
+    assert!(Arc::ptr_eq(&shared.bitmap, &shared.clone().bitmap));
+}
@@ -0,0 +1,139 @@
+//! Read-only view of a serialized bloom filter held in a `bytes::Bytes` buffer.
+
+use std::hash::Hash;
+use std::io;
+
+use bytes::Bytes;
+
+use super::serialize::Header;
+use super::{estimate_items, implied_capacity, BitOffsets, Bloom, BloomHasher, BloomRef};
+
+/// Read-only bloom filter over a reference counted buffer, in the format
+/// produced by `Bloom::to_bytes` or `Bloom::write_to`.
+/// Like `BloomRef`, the bitmap is queried in place, but the view owns a
+/// handle on the buffer: cloning a `BloomBytes` or exporting its buffer is
+/// O(1), so a filter received from the network can be forwarded between
+/// tasks without copying it.
+#[derive(Clone)]
+pub struct BloomBytes {
+    bytes: Bytes,
+    bitmap: Bytes,
+    bitmap_bits: u64,
+    k_num: u32,
+    hasher: BloomHasher,
+    items: u64,
+    capacity: u64,
+}
+
+impl BloomBytes {
+    /// Create a view over a serialized filter, without copying it.
+    /// Trailing bytes after the bitmap are ignored.
+    pub fn from_bytes(bytes: Bytes) -> io::Result<BloomBytes> {
+        let mut rest = &bytes[..];
+        let header = Header::read(&mut rest)?;
+        let start = bytes.len() - rest.len();
+        let end = start + header.words() * 8;
+        if end > bytes.len() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        let bitmap = bytes.slice(start..end);
+        let items = header.items.unwrap_or_else(|| {
+            let bits_set = bitmap.iter().fold(0u64, |acc, byte| acc + byte.count_ones() as u64);
+            estimate_items(bits_set, header.bitmap_bits, header.k_num)
+        });
+        Ok(BloomBytes {
+            bytes: bytes.slice(..end),
+            bitmap,
+            bitmap_bits: header.bitmap_bits,
+            k_num: header.k_num,
+            hasher: header.hasher(),
+            items,
+            capacity: header.capacity.unwrap_or_else(|| implied_capacity(header.bitmap_bits, header.k_num)),
+        })
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        self.bit_offsets(&item).all(|bit_offset| self.bitmap[bit_offset / 8] & (1u8 << (bit_offset % 8)) != 0)
+    }
+
+    /// Return the number of bits in the filter
+    pub fn number_of_bits(&self) -> u64 {
+        self.bitmap_bits
+    }
+
+    /// Return the number of hash functions used for `check`
+    pub fn number_of_hash_functions(&self) -> u32 {
+        self.k_num
+    }
+
+    /// Return the number of items recorded in the filter, see `Bloom::approximate_len`
+    pub fn approximate_len(&self) -> usize {
+        self.items as usize
+    }
+
+    /// Return the number of items the filter was designed for, see `Bloom::capacity`
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    /// Return the serialized filter, sharing the buffer of the view.
+    pub fn bytes(&self) -> Bytes {
+        self.bytes.clone()
+    }
+
+    /// Return a view borrowing the buffer.
+    pub fn as_bloom_ref(&self) -> BloomRef<'_> {
+        BloomRef::from_bytes(&self.bytes).expect("the buffer was validated when the view was built")
+    }
+
+    /// Copy the view into a regular, mutable filter.
+    pub fn to_bloom(&self) -> Bloom {
+        self.as_bloom_ref().to_bloom()
+    }
+
+    fn bit_offsets<T>(&self, item: &T) -> BitOffsets
+        where T: Hash
+    {
+        self.hasher.bit_offsets(item, self.k_num, self.bitmap_bits)
+    }
+}
+
+impl From<&Bloom> for BloomBytes {
+    fn from(bloom: &Bloom) -> BloomBytes {
+        BloomBytes::from_bytes(Bytes::from(bloom.to_bytes())).expect("a serialized filter is always valid")
+    }
+}
+
+impl From<BloomBytes> for Bytes {
+    fn from(view: BloomBytes) -> Bytes {
+        view.bytes
+    }
+}
+
+#[test]
+fn bloom_bytes_check() {
+    let mut bloom = Bloom::new(100, 50);
+    for i in 0..50u32 {
+        bloom.set(i);
+    }
+    let mut buf = bloom.to_bytes();
+    let len = buf.len();
+    buf.extend_from_slice(b"trailer");
+    let bytes = Bytes::from(buf);
+    let view = BloomBytes::from_bytes(bytes.clone()).unwrap();
+    assert_eq!(view.number_of_bits(), bloom.number_of_bits());
+    assert_eq!(view.number_of_hash_functions(), bloom.number_of_hash_functions());
+    for i in 0..200u32 {
+        assert_eq!(view.check(i), bloom.check(i));
+    }
+    assert_eq!(view.approximate_len(), bloom.approximate_len());
+    assert_eq!(view.bytes().as_ptr(), bytes.as_ptr());
+    assert_eq!(view.bytes().len(), len);
+    assert_eq!(view.to_bloom().to_bytes(), bloom.to_bytes());
+    assert_eq!(Bytes::from(BloomBytes::from(&bloom)), bytes.slice(..len));
+    assert!(BloomBytes::from_bytes(bytes.slice(..len - 1)).is_err());
+}
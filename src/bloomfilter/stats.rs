@@ -0,0 +1,78 @@
+//! Summary of the state of a filter, for logs and monitoring.
+
+use std::fmt;
+
+use super::Bloom;
+
+/// Statistics about a filter, as returned by `Bloom::stats`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomStats {
+    /// Size of the bitmap, in bits (m)
+    pub bitmap_bits: u64,
+    /// Size of the bitmap, in bytes
+    pub bitmap_size: usize,
+    /// Number of hash functions (k)
+    pub k_num: u32,
+    /// Ratio of bits that are set
+    pub fill_ratio: f64,
+    /// Number of items estimated from the number of bits set
+    pub estimated_items: usize,
+    /// Number of items the filter was designed for
+    pub capacity: usize,
+    /// Current rate of false positives
+    pub fp_rate: f64,
+}
+
+impl Bloom {
+    /// Return statistics about the filter.
+    /// The bitmap is scanned once to count the bits that are set.
+    pub fn stats(&self) -> BloomStats {
+        let bits_set = self.bitmap.count_ones();
+        let fill_ratio = bits_set as f64 / self.bitmap_bits as f64;
+        BloomStats {
+            bitmap_bits: self.bitmap_bits,
+            bitmap_size: self.bitmap.elems.len() * 8,
+            k_num: self.k_num,
+            fill_ratio,
+            estimated_items: super::estimate_items(bits_set, self.bitmap_bits, self.k_num) as usize,
+            capacity: self.capacity as usize,
+            fp_rate: fill_ratio.powi(self.k_num as i32),
+        }
+    }
+}
+
+impl fmt::Display for BloomStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bits ({} bytes), k={}, {:.2}% full, ~{}/{} items, fp rate {:.3e}",
+               self.bitmap_bits, self.bitmap_size, self.k_num, self.fill_ratio * 100.0,
+               self.estimated_items, self.capacity, self.fp_rate)
+    }
+}
+
+impl fmt::Display for Bloom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bloom({})", self.stats())
+    }
+}
+
+#[test]
+fn bloom_stats() {
+    let mut bloom = Bloom::new(128, 100);
+    let stats = bloom.stats();
+    assert_eq!(stats.bitmap_bits, 1024);
+    assert_eq!(stats.bitmap_size, 128);
+    assert_eq!(stats.k_num, bloom.number_of_hash_functions());
+    assert_eq!(stats.fill_ratio, 0.0);
+    assert_eq!(stats.estimated_items, 0);
+    assert_eq!(stats.capacity, 100);
+    assert_eq!(stats.fp_rate, 0.0);
+
+    for i in 0..50u32 {
+        bloom.set(i);
+    }
+    let stats = bloom.stats();
+    assert_eq!(stats.fp_rate, bloom.current_fp_rate());
+    assert!(stats.fill_ratio > 0.0 && stats.fill_ratio < 1.0);
+    assert_eq!(bloom.to_string(), format!("Bloom({})", stats));
+    assert!(bloom.to_string().starts_with("Bloom(1024 bits (128 bytes), k=8, "));
+}
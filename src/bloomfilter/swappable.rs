@@ -0,0 +1,86 @@
+//! Filter that can be replaced while it is being read.
+
+use std::hash::Hash;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use super::Bloom;
+
+/// Holder of a filter that is periodically rebuilt and republished.
+/// Readers get the current filter with `load`, which never blocks, and
+/// keep using it even if a new one is published with `store` meanwhile.
+/// The filter type defaults to `Bloom`, but any filter can be published,
+/// for example a `SharedBloom`.
+pub struct SwappableBloom<B = Bloom> {
+    current: ArcSwap<B>,
+}
+
+impl<B> SwappableBloom<B> {
+    /// Create a holder publishing an initial filter.
+    pub fn new(bloom: B) -> SwappableBloom<B> {
+        SwappableBloom {
+            current: ArcSwap::from_pointee(bloom),
+        }
+    }
+
+    /// Return the current filter.
+    pub fn load(&self) -> Arc<B> {
+        self.current.load_full()
+    }
+
+    /// Publish a new filter. Readers that already loaded the previous one
+    /// keep it until they drop it.
+    pub fn store(&self, bloom: B) {
+        self.current.store(Arc::new(bloom));
+    }
+
+    /// Publish a new filter, and return the previous one.
+    pub fn swap(&self, bloom: B) -> Arc<B> {
+        self.current.swap(Arc::new(bloom))
+    }
+}
+
+impl SwappableBloom<Bloom> {
+    /// Check if an item is present in the current filter.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        self.current.load().check(item)
+    }
+}
+
+impl<B> From<B> for SwappableBloom<B> {
+    fn from(bloom: B) -> SwappableBloom<B> {
+        SwappableBloom::new(bloom)
+    }
+}
+
+#[test]
+fn swappable_bloom_store() {
+    let mut first = Bloom::new(100, 50);
+    first.set("old");
+    let swappable = Arc::new(SwappableBloom::new(first));
+    let loaded = swappable.load();
+    assert!(swappable.check("old"));
+
+    let reader = {
+        let swappable = swappable.clone();
+        std::thread::spawn(move || {
+            while !swappable.check("new") {
+                std::thread::yield_now();
+            }
+        })
+    };
+    let mut second = Bloom::new(100, 50);
+    second.set("new");
+    swappable.store(second);
+    reader.join().unwrap();
+
+    assert!(loaded.check("old"));
+    assert!(!swappable.check("old"));
+    let previous = swappable.swap(Bloom::new(100, 50));
+    assert!(previous.check("new"));
+    assert!(swappable.load().is_empty());
+}
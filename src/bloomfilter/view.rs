@@ -0,0 +1,118 @@
+//! Read-only view of a serialized bloom filter.
+
+use std::hash::Hash;
+use std::io;
+
+use super::serialize::{self, Header};
+use super::{estimate_items, implied_capacity, BitOffsets, BitVecJournal, Bloom, BloomHasher};
+
+/// Read-only bloom filter over borrowed bytes, in the format produced by
+/// `Bloom::to_bytes` or `Bloom::write_to`.
+/// The bitmap is queried in place: building a view neither allocates nor
+/// copies, so it can sit directly on top of a mmapped file or a network buffer.
+#[derive(Clone)]
+pub struct BloomRef<'a> {
+    bitmap: &'a [u8],
+    bitmap_bits: u64,
+    k_num: u32,
+    hasher: BloomHasher,
+    items: u64,
+    capacity: u64,
+}
+
+impl<'a> BloomRef<'a> {
+    /// Create a view over a serialized filter.
+    /// Trailing bytes after the bitmap are ignored.
+    pub fn from_bytes(bytes: &'a [u8]) -> io::Result<BloomRef<'a>> {
+        let mut rest = bytes;
+        let header = Header::read(&mut rest)?;
+        let bitmap = rest.get(..header.words() * 8)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        Ok(BloomRef {
+            bitmap,
+            bitmap_bits: header.bitmap_bits,
+            k_num: header.k_num,
+            hasher: header.hasher(),
+            items: header.items.unwrap_or_else(|| {
+                let bits_set = bitmap.iter().fold(0u64, |acc, byte| acc + byte.count_ones() as u64);
+                estimate_items(bits_set, header.bitmap_bits, header.k_num)
+            }),
+            capacity: header.capacity.unwrap_or_else(|| implied_capacity(header.bitmap_bits, header.k_num)),
+        })
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        self.bit_offsets(&item).all(|bit_offset| self.bitmap[bit_offset / 8] & (1u8 << (bit_offset % 8)) != 0)
+    }
+
+    /// Return the number of bits in the filter
+    pub fn number_of_bits(&self) -> u64 {
+        self.bitmap_bits
+    }
+
+    /// Return the number of hash functions used for `check`
+    pub fn number_of_hash_functions(&self) -> u32 {
+        self.k_num
+    }
+
+    /// Return the number of items recorded in the filter, see `Bloom::approximate_len`
+    pub fn approximate_len(&self) -> usize {
+        self.items as usize
+    }
+
+    /// Return the number of items the filter was designed for, see `Bloom::capacity`
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    /// Copy the view into a regular, mutable filter.
+    pub fn to_bloom(&self) -> Bloom {
+        let mut elems = Vec::with_capacity(self.bitmap.len() / 8);
+        serialize::decode_words(self.bitmap, &mut elems);
+        Bloom {
+            bitmap: BitVecJournal::from_vec(elems),
+            bitmap_bits: self.bitmap_bits,
+            k_num: self.k_num,
+            hasher: self.hasher.clone(),
+            items: self.items,
+            capacity: self.capacity,
+        }
+    }
+
+    /// Return the serialized bitmap
+    pub(crate) fn bitmap(&self) -> &'a [u8] {
+        self.bitmap
+    }
+
+    pub(crate) fn hasher(&self) -> &BloomHasher {
+        &self.hasher
+    }
+
+    fn bit_offsets<T>(&self, item: &T) -> BitOffsets
+        where T: Hash
+    {
+        self.hasher.bit_offsets(item, self.k_num, self.bitmap_bits)
+    }
+}
+
+#[test]
+fn bloom_ref_check() {
+    let mut bloom = Bloom::new(100, 50);
+    for i in 0..50u32 {
+        bloom.set(i);
+    }
+    let bytes = bloom.to_bytes();
+    let view = BloomRef::from_bytes(&bytes).unwrap();
+    assert_eq!(view.number_of_bits(), bloom.number_of_bits());
+    assert_eq!(view.number_of_hash_functions(), bloom.number_of_hash_functions());
+    for i in 0..200u32 {
+        assert_eq!(view.check(i), bloom.check(i));
+    }
+    assert_eq!(view.approximate_len(), bloom.approximate_len());
+    assert_eq!(view.to_bloom().to_bytes(), bytes);
+    assert!(BloomRef::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
@@ -0,0 +1,315 @@
+//! Write-ahead logging of insertions, so that a filter survives crashes.
+//!
+//! A filter with a write-ahead log is stored in two files:
+//!
+//! * the checkpoint, at the given path: the whole filter, in the format
+//!   described in the `serialize` module
+//! * the log, at the same path with a ".wal" suffix: magic "BLMW" and the
+//!   header of the filter, followed by one record per item that changed the
+//!   filter: the hash pair of the item, as two little-endian u64, and the
+//!   CRC-32 of these 16 bytes, little-endian
+//!
+//! Records are appended before the filter is updated in memory. Recovering
+//! loads the checkpoint and replays the log. A record that was only partly
+//! written when the process crashed is detected and dropped, and a log that
+//! is missing or whose header is incomplete is considered empty.
+//! A checkpoint writes the whole filter, then empties the log. Both files
+//! are written to temporary files and renamed: a crash between the renames
+//! leaves the previous log next to the new checkpoint, and replaying it is
+//! harmless, since its items are all in the checkpoint.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::crc32;
+use super::serialize::Header;
+use super::{Bloom, BloomError};
+
+const MAGIC: &[u8; 4] = b"BLMW";
+
+/// Length of a log record
+const RECORD_LEN: usize = 16 + 4;
+
+/// Bloom filter whose insertions are logged to a file before being applied
+pub struct WalBloom {
+    bloom: Bloom,
+    path: PathBuf,
+    log: File,
+    log_records: u64,
+    checkpoint_interval: Option<u64>,
+}
+
+impl WalBloom {
+    /// Write a checkpoint of the filter at path, and start an empty log next to it.
+    /// Existing files are replaced.
+    pub fn create<P>(path: P, bloom: Bloom) -> io::Result<WalBloom>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref().to_path_buf();
+        write_checkpoint(&path, &bloom)?;
+        let log = create_log(&path, &bloom)?;
+        sync_dir(&path)?;
+        Ok(WalBloom {
+            bloom,
+            path,
+            log,
+            log_records: 0,
+            checkpoint_interval: None,
+        })
+    }
+
+    /// Load the checkpoint at path, and replay the log next to it.
+    /// If the log ends with a partly written record, that record is dropped.
+    /// If the log is missing, or its header is incomplete, an empty log is
+    /// created.
+    pub fn recover<P>(path: P) -> io::Result<WalBloom>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref().to_path_buf();
+        let mut bloom = Bloom::read_from(&mut BufReader::new(File::open(&path)?))?;
+        let log_path = log_path(&path);
+        let replayed = match File::open(&log_path) {
+            Ok(file) => replay(&mut bloom, &mut BufReader::new(file))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let (log, log_records) = match replayed {
+            Some((valid_len, log_records)) => {
+                let log = OpenOptions::new().append(true).open(&log_path)?;
+                log.set_len(valid_len)?;
+                (log, log_records)
+            }
+            None => {
+                let log = create_log(&path, &bloom)?;
+                sync_dir(&path)?;
+                (log, 0)
+            }
+        };
+        Ok(WalBloom {
+            bloom,
+            path,
+            log,
+            log_records,
+            checkpoint_interval: None,
+        })
+    }
+
+    /// Automatically write a checkpoint once the log holds interval records.
+    pub fn with_checkpoint_interval(self, interval: u64) -> WalBloom {
+        assert!(interval > 0);
+        WalBloom {
+            checkpoint_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Record the presence of an item.
+    /// If the item changes the filter, it is logged first. The record is
+    /// handed to the operating system before returning, so that it survives
+    /// a crash of the process; use `sync` to also survive a crash of the system.
+    pub fn set<T>(&mut self, item: T) -> io::Result<()>
+        where T: Hash
+    {
+        let hashes = self.bloom.hasher.hash_pair(&item);
+        if self.bloom.check_hashes(hashes) {
+            return Ok(());
+        }
+        let mut record = [0u8; RECORD_LEN];
+        record[..8].copy_from_slice(&hashes[0].to_le_bytes());
+        record[8..16].copy_from_slice(&hashes[1].to_le_bytes());
+        let crc = crc32::checksum(&record[..16]);
+        record[16..].copy_from_slice(&crc.to_le_bytes());
+        self.log.write_all(&record)?;
+        self.bloom.check_and_set_hashes(hashes);
+        self.log_records += 1;
+        if self.checkpoint_interval.is_some_and(|interval| self.log_records >= interval) {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Check if an item is present in the set.
+    /// There can be false positives, but no false negatives.
+    pub fn check<T>(&self, item: T) -> bool
+        where T: Hash
+    {
+        self.bloom.check(item)
+    }
+
+    /// Wait for the logged records to reach the disk
+    pub fn sync(&self) -> io::Result<()> {
+        self.log.sync_data()
+    }
+
+    /// Write the whole filter to the checkpoint, and empty the log.
+    /// The checkpoint is written to a temporary file first, and then renamed,
+    /// so that a crash never leaves a partly written checkpoint.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        write_checkpoint(&self.path, &self.bloom)?;
+        self.log = create_log(&self.path, &self.bloom)?;
+        sync_dir(&self.path)?;
+        self.log_records = 0;
+        Ok(())
+    }
+
+    /// Return the number of records in the log since the last checkpoint
+    pub fn log_records(&self) -> u64 {
+        self.log_records
+    }
+
+    /// Return the filter
+    pub fn bloom(&self) -> &Bloom {
+        &self.bloom
+    }
+
+    /// Return the filter, and close the log
+    pub fn into_bloom(self) -> Bloom {
+        self.bloom
+    }
+}
+
+fn log_path(path: &Path) -> PathBuf {
+    let mut log_path = OsString::from(path.as_os_str());
+    log_path.push(".wal");
+    PathBuf::from(log_path)
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = OsString::from(path.as_os_str());
+    tmp_path.push(".tmp");
+    PathBuf::from(tmp_path)
+}
+
+fn write_checkpoint(path: &Path, bloom: &Bloom) -> io::Result<()> {
+    let tmp_path = tmp_path(path);
+    let mut w = io::BufWriter::new(File::create(&tmp_path)?);
+    bloom.write_to(&mut w)?;
+    w.flush()?;
+    w.get_ref().sync_data()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Write an empty log to a temporary file, rename it over the log, and
+/// return it, positioned for appending records
+fn create_log(path: &Path, bloom: &Bloom) -> io::Result<File> {
+    let log_path = log_path(path);
+    let tmp_path = tmp_path(&log_path);
+    let mut log = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+    log.write_all(MAGIC)?;
+    log.write_all(&Header::for_bloom(bloom).encode())?;
+    log.sync_data()?;
+    fs::rename(&tmp_path, &log_path)?;
+    Ok(log)
+}
+
+/// Wait for the renames in the directory of path to reach the disk.
+/// Directories can't be synced on all platforms, where this does nothing.
+fn sync_dir(path: &Path) -> io::Result<()> {
+    if !cfg!(unix) {
+        return Ok(());
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// Replay the records of a log into a filter, and return the length of
+/// the log up to the last valid record, and the number of records.
+/// Return None if the header of the log is incomplete.
+fn replay<R: Read>(bloom: &mut Bloom, reader: &mut R) -> io::Result<Option<(u64, u64)>> {
+    let mut magic = [0u8; 4];
+    let header = match reader.read_exact(&mut magic).and_then(|_| Header::read(reader)) {
+        Ok(header) => header,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if &magic != MAGIC {
+        return Err(BloomError::corrupt("not a bloom filter log"));
+    }
+    if header.bitmap_bits != bloom.bitmap_bits || header.k_num != bloom.k_num ||
+       !header.hasher().is_compatible(&bloom.hasher) {
+        return Err(BloomError::corrupt("log doesn't match the checkpoint"));
+    }
+    let mut valid_len = (MAGIC.len() + Header::for_bloom(bloom).encode().len()) as u64;
+    let mut log_records = 0;
+    let mut record = [0u8; RECORD_LEN];
+    while read_record(reader, &mut record)? {
+        if crc32::checksum(&record[..16]).to_le_bytes() != record[16..] {
+            break;
+        }
+        let mut h0 = [0u8; 8];
+        let mut h1 = [0u8; 8];
+        h0.copy_from_slice(&record[..8]);
+        h1.copy_from_slice(&record[8..16]);
+        bloom.check_and_set_hashes([u64::from_le_bytes(h0), u64::from_le_bytes(h1)]);
+        valid_len += RECORD_LEN as u64;
+        log_records += 1;
+    }
+    Ok(Some((valid_len, log_records)))
+}
+
+/// Read a whole record, and return false at the end of the log,
+/// including when the last record is incomplete
+fn read_record<R: Read>(r: &mut R, record: &mut [u8; RECORD_LEN]) -> io::Result<bool> {
+    match r.read_exact(record) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[test]
+fn wal_bloom_recover() {
+    let path = std::env::temp_dir().join(format!("bloomfilter-wal-{}", std::process::id()));
+    let mut wal = WalBloom::create(&path, Bloom::new(1024, 200)).unwrap();
+    for i in 0..100u32 {
+        wal.set(i).unwrap();
+    }
+    wal.set(0u32).unwrap();
+    assert_eq!(wal.log_records(), 100);
+    let expected = wal.bloom().to_bytes();
+    drop(wal);
+
+    // Partly written record
+    let mut log = OpenOptions::new().append(true).open(log_path(&path)).unwrap();
+    log.write_all(&[1, 2, 3]).unwrap();
+    drop(log);
+
+    let mut wal = WalBloom::recover(&path).unwrap().with_checkpoint_interval(50);
+    assert_eq!(wal.log_records(), 100);
+    assert_eq!(wal.bloom().to_bytes(), expected);
+    wal.checkpoint().unwrap();
+    assert_eq!(wal.log_records(), 0);
+    for i in 100..200u32 {
+        wal.set(i).unwrap();
+    }
+    let log_records = wal.log_records();
+    assert!(log_records < 50);
+    let expected = wal.bloom().to_bytes();
+    drop(wal);
+
+    let wal = WalBloom::recover(&path).unwrap();
+    assert_eq!(wal.log_records(), log_records);
+    assert!((0..200u32).all(|i| wal.check(i)));
+    assert_eq!(wal.bloom().to_bytes(), expected);
+    drop(wal);
+
+    // Log whose header was partly written, then missing log
+    fs::write(log_path(&path), &MAGIC[..3]).unwrap();
+    let wal = WalBloom::recover(&path).unwrap();
+    assert_eq!(wal.log_records(), 0);
+    assert!((0..100u32).all(|i| wal.check(i)));
+    drop(wal);
+    fs::remove_file(log_path(&path)).unwrap();
+    let mut wal = WalBloom::recover(&path).unwrap();
+    wal.set(1000u32).unwrap();
+    drop(wal);
+    assert_eq!(WalBloom::recover(&path).unwrap().log_records(), 1);
+    fs::remove_file(log_path(&path)).unwrap();
+    fs::remove_file(&path).unwrap();
+}
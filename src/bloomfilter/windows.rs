@@ -0,0 +1,107 @@
+//! Recording the windows of a sequence, such as the k-mers of a DNA sequence.
+//!
+//! Every window of k consecutive bytes is hashed in place, as `set_bytes`
+//! does, so a window recorded this way can also be checked with
+//! `check_bytes`. The canonical variants record a window or its reverse
+//! complement, whichever is the smallest, so that a sequence and its reverse
+//! complement share their k-mers.
+
+use super::Bloom;
+
+impl Bloom {
+    /// Record the presence of each window of k bytes of a sequence, and
+    /// return how many of them were definitely new.
+    /// Sequences shorter than k have no windows.
+    pub fn set_windows(&mut self, seq: &[u8], k: usize) -> usize {
+        let mut new = 0;
+        for_each_window(seq, k, false, |bytes| {
+            new += self.insert_bytes(bytes) as usize;
+        });
+        new
+    }
+
+    /// Return how many windows of k bytes of a sequence may be present in
+    /// the set. All the windows recorded with `set_windows` are found.
+    pub fn check_windows(&self, seq: &[u8], k: usize) -> usize {
+        let mut found = 0;
+        for_each_window(seq, k, false, |bytes| {
+            found += self.check_bytes(bytes) as usize;
+        });
+        found
+    }
+
+    /// Record the presence of the canonical form of each window of k bytes
+    /// of a DNA sequence, and return how many of them were definitely new.
+    /// A, C, G and T, in upper or lower case, are complemented; other bytes,
+    /// such as N, are left as they are.
+    pub fn set_canonical_windows(&mut self, seq: &[u8], k: usize) -> usize {
+        let mut new = 0;
+        for_each_window(seq, k, true, |bytes| {
+            new += self.insert_bytes(bytes) as usize;
+        });
+        new
+    }
+
+    /// Return how many windows of k bytes of a DNA sequence may be present
+    /// in the set, in their canonical form, see `set_canonical_windows`.
+    pub fn check_canonical_windows(&self, seq: &[u8], k: usize) -> usize {
+        let mut found = 0;
+        for_each_window(seq, k, true, |bytes| {
+            found += self.check_bytes(bytes) as usize;
+        });
+        found
+    }
+}
+
+/// Call f with each window of a sequence, or its canonical form.
+/// Reverse complements are built in a single buffer, reused for all windows.
+fn for_each_window<F>(seq: &[u8], k: usize, canonical: bool, mut f: F)
+    where F: FnMut(&[u8])
+{
+    assert!(k > 0);
+    let mut reverse = Vec::with_capacity(if canonical { k } else { 0 });
+    for window in seq.windows(k) {
+        if !canonical {
+            f(window);
+            continue;
+        }
+        reverse.clear();
+        reverse.extend(window.iter().rev().map(|&base| complement(base)));
+        f(if reverse[..] < *window { &reverse } else { window });
+    }
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        other => other,
+    }
+}
+
+#[test]
+fn bloom_sequence_windows() {
+    let mut bloom = Bloom::new_for_fp_rate(1000, 0.001);
+    let seq = b"ACGTTGCAAGGCTTAACCGGTAGC";
+    assert_eq!(bloom.set_windows(seq, 5), 20);
+    assert_eq!(bloom.set_windows(seq, 5), 0);
+    assert_eq!(bloom.check_windows(seq, 5), 20);
+    assert!(bloom.check_bytes(b"GCAAG") && !bloom.check(&b"GCAAG"[..]));
+    assert_eq!(bloom.check_windows(b"ACGT", 5), 0);
+    assert_eq!(bloom.check_windows(b"TTGCAATTTTT", 5), 2);
+
+    let mut canonical = Bloom::new_for_fp_rate(1000, 0.001);
+    // Two pairs of windows are reverse complements of each other
+    assert_eq!(canonical.set_canonical_windows(seq, 5), 18);
+    // ACGTT and its reverse complement AACGT are the same k-mer
+    assert!(canonical.check_bytes(b"AACGT") && !canonical.check_bytes(b"ACGTT"));
+    let reverse: Vec<u8> = seq.iter().rev().map(|&base| complement(base)).collect();
+    assert_eq!(canonical.check_canonical_windows(&reverse, 5), 20);
+    assert_eq!(bloom.check_windows(&reverse, 5), 4);
+}
@@ -0,0 +1,275 @@
+//! Compact frames for exchanging filters between peers.
+//!
+//! Frames are meant to be gossiped over untrusted connections: they are
+//! checksummed, the size of the bitmap a frame can describe is bounded by
+//! the receiver, the number of hash functions by `MAX_K_NUM`, and the bitmap
+//! of a sparse filter is compressed. A frame is:
+//!
+//! * version: u8 (currently 1)
+//! * flags: u8; bit 0 is set if the bitmap is compressed, bit 1 if SipHash
+//!   keys follow the parameters
+//! * k_num, bitmap_bits, number of items, capacity: LEB128 varints
+//! * hash algorithm: u8
+//! * SipHash keys: 4 u64, little-endian, if bit 1 of the flags is set;
+//!   otherwise the filter uses the default keys
+//! * bitmap: ceil(bitmap_bits / 64) little-endian u64 words, as in the
+//!   `serialize` module. When compressed, it is a sequence of runs, each made
+//!   of a number of zero bytes and a number of literal bytes, as varints,
+//!   followed by the literal bytes.
+//! * CRC-32 of all the previous bytes, u32, little-endian
+//!
+//! The version comes first and is checked before anything else, so that
+//! later versions can change the rest of the frame. Peers exchange the range
+//! of versions they support, and `negotiate` picks the one to use.
+
+use std::convert::TryFrom;
+use std::io;
+
+use super::crc32;
+use super::serialize::{self, read_u64, Header};
+use super::{Bloom, BloomError, HashAlgorithm, SipKeys};
+
+/// Latest version of the frame format
+pub const VERSION: u8 = 1;
+
+/// Oldest version of the frame format that can be encoded and decoded
+pub const MIN_VERSION: u8 = 1;
+
+const FLAG_COMPRESSED: u8 = 1;
+const FLAG_SIP_KEYS: u8 = 2;
+
+/// Shortest run of zero bytes worth ending a run of literal bytes
+const MIN_ZERO_RUN: usize = 4;
+
+/// Return the version to use with a peer supporting versions peer_min to
+/// peer_max: the latest version supported by both sides.
+pub fn negotiate(peer_min: u8, peer_max: u8) -> Result<u8, BloomError> {
+    let version = peer_max.min(VERSION);
+    if version < peer_min.max(MIN_VERSION) {
+        return Err(BloomError::UnsupportedVersion { version: peer_max });
+    }
+    Ok(version)
+}
+
+/// Encode a filter into a frame of the given version.
+/// The bitmap is compressed if that makes the frame smaller.
+pub fn encode(bloom: &Bloom, version: u8) -> Result<Vec<u8>, BloomError> {
+    if !(MIN_VERSION..=VERSION).contains(&version) {
+        return Err(BloomError::UnsupportedVersion { version });
+    }
+    let sip_keys = bloom.sip_keys();
+    let mut bitmap = Vec::with_capacity(bloom.bitmap.elems.len() * 8);
+    serialize::encode_words(&bloom.bitmap.elems, &mut bitmap);
+    let compressed = compress(&bitmap);
+    let mut flags = 0;
+    if compressed.len() < bitmap.len() {
+        flags |= FLAG_COMPRESSED;
+        bitmap = compressed;
+    }
+    if sip_keys != SipKeys::default() {
+        flags |= FLAG_SIP_KEYS;
+    }
+
+    let mut frame = Vec::with_capacity(2 + 4 * 10 + 1 + 32 + bitmap.len() + 4);
+    frame.push(version);
+    frame.push(flags);
+    for value in [bloom.k_num as u64, bloom.bitmap_bits, bloom.items, bloom.capacity] {
+        write_varint(&mut frame, value);
+    }
+    frame.push(bloom.hash_algorithm().id());
+    if flags & FLAG_SIP_KEYS != 0 {
+        for (k0, k1) in sip_keys.0.iter() {
+            frame.extend_from_slice(&k0.to_le_bytes());
+            frame.extend_from_slice(&k1.to_le_bytes());
+        }
+    }
+    frame.extend_from_slice(&bitmap);
+    let crc = crc32::checksum(&frame);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    Ok(frame)
+}
+
+/// Decode a frame, rejecting it if its version is not supported, if it
+/// is corrupt, or if its bitmap is larger than max_bitmap_size bytes.
+pub fn decode(frame: &[u8], max_bitmap_size: usize) -> io::Result<Bloom> {
+    let version = *frame.first().ok_or_else(|| BloomError::corrupt("empty frame"))?;
+    if !(MIN_VERSION..=VERSION).contains(&version) {
+        return Err(BloomError::UnsupportedVersion { version }.into());
+    }
+    if frame.len() < 6 {
+        return Err(BloomError::corrupt("truncated frame"));
+    }
+    let (data, crc) = frame.split_at(frame.len() - 4);
+    if crc32::checksum(data).to_le_bytes() != crc {
+        return Err(BloomError::corrupt("frame checksum mismatch"));
+    }
+
+    let flags = data[1];
+    if flags & !(FLAG_COMPRESSED | FLAG_SIP_KEYS) != 0 {
+        return Err(BloomError::corrupt("unknown frame flags"));
+    }
+    let mut buf = &data[2..];
+    let k_num = u32::try_from(read_varint(&mut buf)?).map_err(|_| BloomError::InvalidParams)?;
+    let bitmap_bits = read_varint(&mut buf)?;
+    let items = read_varint(&mut buf)?;
+    let capacity = read_varint(&mut buf)?;
+    let algorithm = HashAlgorithm::from_id(read_bytes(&mut buf, 1)?[0])
+        .ok_or_else(|| BloomError::corrupt("unsupported hash algorithm"))?;
+    let mut sip_keys = SipKeys::default();
+    if flags & FLAG_SIP_KEYS != 0 {
+        for (k0, k1) in sip_keys.0.iter_mut() {
+            *k0 = read_u64(&mut buf)?;
+            *k1 = read_u64(&mut buf)?;
+        }
+    }
+    let header = Header {
+        bitmap_bits,
+        k_num,
+        sip_keys,
+        algorithm,
+        items: Some(items),
+        capacity: Some(capacity),
+    };
+    header.validate(None)?;
+    let bitmap_size = header.words().checked_mul(8).filter(|&size| size <= max_bitmap_size)
+        .ok_or(BloomError::InvalidParams)?;
+
+    let mut elems = Vec::with_capacity(bitmap_size / 8);
+    if flags & FLAG_COMPRESSED != 0 {
+        serialize::decode_words(&decompress(buf, bitmap_size)?, &mut elems);
+    } else if buf.len() == bitmap_size {
+        serialize::decode_words(buf, &mut elems);
+    } else {
+        return Err(BloomError::corrupt("bitmap length mismatch"));
+    }
+    Ok(header.into_bloom(elems))
+}
+
+/// Split bytes into runs of zero bytes followed by literal bytes
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let zeros = bytes[i..].iter().take_while(|&&byte| byte == 0).count();
+        i += zeros;
+        let start = i;
+        while i < bytes.len() && !bytes[i..].starts_with(&[0; MIN_ZERO_RUN]) {
+            i += 1;
+        }
+        write_varint(&mut out, zeros as u64);
+        write_varint(&mut out, (i - start) as u64);
+        out.extend_from_slice(&bytes[start..i]);
+    }
+    out
+}
+
+/// Expand runs into exactly len bytes
+fn decompress(mut buf: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    while out.len() < len {
+        let zeros = read_varint(&mut buf)?;
+        let literal = read_varint(&mut buf)?;
+        let end = zeros.checked_add(literal).and_then(|run| run.checked_add(out.len() as u64));
+        if end.is_none_or(|end| end > len as u64) || zeros + literal == 0 {
+            return Err(BloomError::corrupt("invalid bitmap run"));
+        }
+        out.resize(out.len() + zeros as usize, 0);
+        out.extend_from_slice(read_bytes(&mut buf, literal as usize)?);
+    }
+    if !buf.is_empty() {
+        return Err(BloomError::corrupt("bitmap length mismatch"));
+    }
+    Ok(out)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(buf: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_bytes(buf, 1)?[0];
+        if shift == 63 && byte > 1 {
+            break;
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(BloomError::corrupt("varint overflow"))
+}
+
+fn read_bytes<'a>(buf: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if buf.len() < n {
+        return Err(BloomError::corrupt("truncated frame"));
+    }
+    let (bytes, rest) = buf.split_at(n);
+    *buf = rest;
+    Ok(bytes)
+}
+
+#[test]
+fn wire_frames() {
+    let mut bloom = Bloom::new(4096, 1000);
+    bloom.insert_all(0..20u32);
+    let frame = encode(&bloom, VERSION).unwrap();
+    assert_eq!(frame[1], FLAG_COMPRESSED);
+    assert!(frame.len() < bloom.to_bytes().len() / 2);
+    let decoded = decode(&frame, 4096).unwrap();
+    assert_eq!(decoded.to_bytes(), bloom.to_bytes());
+
+    let mut dense = Bloom::new(256, 1000).with_sip_keys(SipKeys([(1, 2), (3, 4)]));
+    dense.insert_all(0..1000u32);
+    let frame = encode(&dense, VERSION).unwrap();
+    assert_eq!(frame[1], FLAG_SIP_KEYS);
+    assert_eq!(decode(&frame, 256).unwrap().to_bytes(), dense.to_bytes());
+
+    let err = decode(&frame, 128).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::InvalidParams));
+    let mut corrupt = frame.clone();
+    corrupt[40] ^= 1;
+    let err = decode(&corrupt, 256).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::CorruptData { reason: "frame checksum mismatch" }));
+    let err = decode(&[VERSION + 1, 0, 0, 0, 0, 0], 256).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::UnsupportedVersion { version: VERSION + 1 }));
+    assert!(decode(&frame[..frame.len() / 2], 256).is_err());
+
+    // A single bitmap byte, checked with u32::MAX hash functions
+    let mut frame = vec![VERSION, 0];
+    for value in [u32::MAX as u64, 8, 0, 1] {
+        write_varint(&mut frame, value);
+    }
+    frame.extend_from_slice(&[HashAlgorithm::default().id(), 0xff]);
+    let crc = crc32::checksum(&frame);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    let err = decode(&frame, 64).err().unwrap();
+    assert_eq!(BloomError::from_io_error(&err), Some(&BloomError::InvalidParams));
+
+    assert_eq!(negotiate(1, 3), Ok(VERSION));
+    assert_eq!(negotiate(2, 3), Err(BloomError::UnsupportedVersion { version: 3 }));
+    assert_eq!(encode(&bloom, 0), Err(BloomError::UnsupportedVersion { version: 0 }));
+}
+
+#[test]
+fn wire_bitmap_runs() {
+    let bytes = [0, 0, 0, 0, 0, 7, 0, 9, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0];
+    let compressed = compress(&bytes);
+    assert_eq!(compressed, [5, 3, 7, 0, 9, 8, 3, 3, 0, 0]);
+    assert_eq!(decompress(&compressed, bytes.len()).unwrap(), bytes);
+    assert!(decompress(&compressed, bytes.len() - 1).is_err());
+    assert!(decompress(&[0, 0], 8).is_err());
+
+    let mut varints = Vec::new();
+    write_varint(&mut varints, u64::MAX);
+    write_varint(&mut varints, 300);
+    let mut buf = &varints[..];
+    assert_eq!(read_varint(&mut buf).unwrap(), u64::MAX);
+    assert_eq!(read_varint(&mut buf).unwrap(), 300);
+    assert!(read_varint(&mut &[0xff; 11][..]).is_err());
+}